@@ -0,0 +1,121 @@
+#[macro_use]
+extern crate criterion;
+extern crate akasabi;
+
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+
+use criterion::Criterion;
+use criterion::black_box;
+
+use akasabi::http::parser::HeadParser;
+
+const REQUEST: &[u8] = b"GET /search?q=akasabi&lang=ja HTTP/1.1\r\n\
+Host: www.example.com\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8\r\n\
+Accept-Language: ja,en-US;q=0.7,en;q=0.3\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Referer: https://www.example.com/\r\n\
+Cookie: session=0123456789abcdef0123456789abcdef; csrf_token=fedcba9876543210\r\n\
+Connection: keep-alive\r\n\
+Upgrade-Insecure-Requests: 1\r\n\
+Sec-Fetch-Dest: document\r\n\
+Sec-Fetch-Mode: navigate\r\n\
+\r\n";
+
+// Hands out at most `chunk` bytes per read, like a slow network.
+struct Chunked<'a> {
+	data: &'a [u8],
+	chunk: usize,
+}
+
+impl<'a> Read for Chunked<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let size = cmp::min(cmp::min(self.chunk, buf.len()), self.data.len());
+		buf[.. size].copy_from_slice(&self.data[.. size]);
+		self.data = &self.data[size ..];
+		Ok(size)
+	}
+}
+
+// The reader `HttpHandler` used before: each line is copied out and the
+// rest of the buffer shifted down, and the buffer is zeroed per request.
+struct LineReader {
+	offset: usize,
+	buffer: [u8; 8192],
+}
+
+impl LineReader {
+	fn read_line<R: Read>(&mut self, stream: &mut R) -> Option<Vec<u8>> {
+		loop {
+			if self.offset > 0 {
+				if let Some(pos) = self.buffer[.. self.offset].iter().position(|&x| x == b'\n') {
+					let eol = if pos > 0 && self.buffer[pos - 1] == b'\r' { pos - 1 } else { pos };
+					let line = self.buffer[0 .. eol].to_vec();
+					if pos + 1 < self.offset {
+						for i in pos + 1 .. self.offset {
+							self.buffer[i - pos - 1] = self.buffer[i];
+						}
+						self.offset = self.offset - pos - 1;
+					} else {
+						self.offset = 0;
+					}
+					return Some(line)
+				}
+			}
+			if self.offset < self.buffer.len() {
+				let size = stream.read(&mut self.buffer[self.offset ..]).unwrap();
+				if size == 0 { break; }
+				self.offset = self.offset + size;
+			} else {
+				break;
+			}
+		}
+		None
+	}
+
+	fn read_header<R: Read>(&mut self, stream: &mut R) -> Vec<Vec<u8>> {
+		self.offset = 0;
+		for i in self.buffer.iter_mut() {
+			*i = 0;
+		}
+		let mut lines = Vec::new();
+		while let Some(line) = self.read_line(stream) {
+			if line.is_empty() { break; }
+			lines.push(line);
+		}
+		lines
+	}
+}
+
+fn read_header<R: Read>(parser: &mut HeadParser, buffer: &mut [u8], stream: &mut R) -> usize {
+	parser.reset();
+	let mut filled = 0;
+	loop {
+		if parser.advance(&buffer[.. filled]).is_some() {
+			return parser.header(buffer).lines.len();
+		}
+		filled += stream.read(&mut buffer[filled ..]).unwrap();
+	}
+}
+
+fn bench_parser(c: &mut Criterion) {
+	for &chunk in &[REQUEST.len(), 64] {
+		let mut group = c.benchmark_group(format!("read_header/{}", chunk));
+		group.bench_function("line_copy", |b| {
+			let mut reader = LineReader { offset: 0, buffer: [0; 8192] };
+			b.iter(|| reader.read_header(&mut Chunked { data: black_box(REQUEST), chunk: chunk }).len())
+		});
+		group.bench_function("head_parser", |b| {
+			let mut parser = HeadParser::new();
+			let mut buffer = vec![0; 8192];
+			b.iter(|| read_header(&mut parser, &mut buffer, &mut Chunked { data: black_box(REQUEST), chunk: chunk }))
+		});
+		group.finish();
+	}
+}
+
+criterion_group!(benches, bench_parser);
+criterion_main!(benches);
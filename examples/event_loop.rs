@@ -0,0 +1,26 @@
+extern crate akasabi;
+
+use std::net::TcpListener;
+
+use akasabi::http::server::Server;
+use akasabi::Handler;
+use akasabi::Request;
+use akasabi::Response;
+
+use akasabi::html::builder::HTML;
+use akasabi::html::builder::h1;
+
+struct MyHandler;
+
+impl Handler for MyHandler {
+	fn handle(&self, _: &Request) -> Response {
+		let mut html = HTML::new("akasabi", "ja");
+		html.body.push_tag(h1().text("It works!"));
+		Response::from_string(html.to_string())
+	}
+}
+
+fn main() {
+	let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+	Server::new(MyHandler).run(listener).unwrap();
+}
@@ -0,0 +1,43 @@
+extern crate akasabi;
+
+use std::net::TcpListener;
+use std::path::Path;
+
+use akasabi::http::HttpHandler;
+use akasabi::Handler;
+use akasabi::Request;
+use akasabi::Response;
+
+use akasabi::html::template::Context;
+use akasabi::html::template::Templates;
+
+struct MyHandler<'a> {
+	templates: &'a Templates,
+}
+
+impl<'a> Handler for MyHandler<'a> {
+	fn handle(&self, req: &Request) -> Response {
+		let mut context = Context::new();
+		context.set("title", "GET");
+		let mut params: Vec<Context> = Vec::new();
+		for param in req.get_params() {
+			let mut p = Context::new();
+			p.set("name", param.name());
+			p.set("value", param.value());
+			params.push(p);
+		}
+		context.set("params", params);
+		self.templates.response("params.html", &context)
+	}
+}
+
+fn main() {
+	let templates = match Templates::load(Path::new("examples/templates")) {
+		Ok(templates) => templates,
+		Err(e) => panic!("{}", e),
+	};
+	let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+	for stream in listener.incoming() {
+		HttpHandler::new(MyHandler { templates: &templates }).handle(stream.unwrap());
+	}
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::from_utf8;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use ExtendedRequest;
+use Handler;
+use Request;
+use Response;
+
+use crypto;
+use crypto::bcrypt;
+use crypto::digest;
+
+pub const USER_ATTR: &str = "user";
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum DigestAlgorithm {
+	Md5,
+	Sha256,
+}
+
+impl DigestAlgorithm {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			DigestAlgorithm::Md5 => "MD5",
+			DigestAlgorithm::Sha256 => "SHA-256",
+		}
+	}
+	// The digest of the data in lowercase hex, as Digest authentication
+	// uses it throughout.
+	pub fn hash(&self, data: &[u8]) -> String {
+		match *self {
+			DigestAlgorithm::Md5 => crypto::encode_hex(&digest::md5(data)),
+			DigestAlgorithm::Sha256 => crypto::encode_hex(&digest::sha256(data)),
+		}
+	}
+}
+
+pub trait CredentialStore {
+	fn verify(&self, user: &str, password: &str) -> bool;
+	// Returns H(user:realm:password) for Digest authentication. Stores that
+	// keep only one-way password hashes can't support Digest.
+	fn digest_ha1(&self, _user: &str, _realm: &str, _algorithm: DigestAlgorithm) -> Option<String> {
+		None
+	}
+}
+
+// Passwords kept in memory, which support both Basic and Digest.
+pub struct MemoryStore {
+	users: HashMap<String, String>,
+}
+
+impl Default for MemoryStore {
+	fn default() -> MemoryStore {
+		MemoryStore::new()
+	}
+}
+
+impl MemoryStore {
+	pub fn new() -> MemoryStore {
+		MemoryStore {
+			users: HashMap::new(),
+		}
+	}
+	pub fn insert(&mut self, user: &str, password: &str) {
+		self.users.insert(String::from(user), String::from(password));
+	}
+}
+
+impl CredentialStore for MemoryStore {
+	fn verify(&self, user: &str, password: &str) -> bool {
+		match self.users.get(user) {
+			Some(p) => crypto::constant_time_eq(p.as_bytes(), password.as_bytes()),
+			None => false,
+		}
+	}
+	fn digest_ha1(&self, user: &str, realm: &str, algorithm: DigestAlgorithm) -> Option<String> {
+		self.users.get(user).map(|p| algorithm.hash(format!("{}:{}:{}", user, realm, p).as_bytes()))
+	}
+}
+
+// Users from an Apache htpasswd file, read once. Entries hashed with
+// bcrypt (`$2y$`) or SHA-1 (`{SHA}`) are supported, and any other never
+// matches. Only Basic authentication can be used with these.
+pub struct Htpasswd {
+	entries: HashMap<String, String>,
+}
+
+impl Htpasswd {
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Htpasswd> {
+		let mut content = String::new();
+		File::open(path)?.read_to_string(&mut content)?;
+		Ok(Htpasswd::parse(&content))
+	}
+	pub fn parse(content: &str) -> Htpasswd {
+		let mut entries = HashMap::new();
+		for line in content.lines().map(|l| l.trim()) {
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			if let Some(pos) = line.find(':') {
+				entries.insert(String::from(&line[.. pos]), String::from(&line[pos + 1 ..]));
+			}
+		}
+		Htpasswd {
+			entries: entries,
+		}
+	}
+}
+
+impl CredentialStore for Htpasswd {
+	fn verify(&self, user: &str, password: &str) -> bool {
+		match self.entries.get(user) {
+			Some(hash) if hash.starts_with("$2") => bcrypt::verify(password.as_bytes(), hash),
+			Some(hash) if hash.starts_with("{SHA}") => {
+				let expected = crypto::encode_base64(&digest::sha1(password.as_bytes()));
+				crypto::constant_time_eq(expected.as_bytes(), &hash.as_bytes()[5 ..])
+			}
+			_ => false,
+		}
+	}
+}
+
+// Writes a quoted string for an auth header parameter.
+pub fn quote(s: &str) -> String {
+	format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Parses the comma separated `name=value` pairs of a Digest header, where
+// values may be quoted strings with backslash escapes.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+	let mut params = Vec::new();
+	let mut chars = s.chars().peekable();
+	loop {
+		while chars.peek().is_some_and(|&c| c == ' ' || c == ',') {
+			chars.next();
+		}
+		let mut name = String::new();
+		for c in chars.by_ref() {
+			if c == '=' {
+				break;
+			}
+			name.push(c);
+		}
+		if name.is_empty() {
+			break;
+		}
+		let mut value = String::new();
+		if chars.peek() == Some(&'"') {
+			chars.next();
+			while let Some(c) = chars.next() {
+				match c {
+					'"' => break,
+					'\\' => value.extend(chars.next()),
+					_ => value.push(c),
+				}
+			}
+		} else {
+			while let Some(&c) = chars.peek() {
+				if c == ',' {
+					break;
+				}
+				value.push(c);
+				chars.next();
+			}
+		}
+		params.push((name.trim().to_ascii_lowercase(), String::from(value.trim())));
+	}
+	params
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+enum Failure {
+	Missing,
+	Stale,
+}
+
+// Requires HTTP Basic or Digest authentication before passing requests to
+// the wrapped handler, which finds the user with `Request::user`. Digest
+// nonces are signed with the time they were issued, so they need no
+// server state, but nonce counts aren't tracked and a response can be
+// replayed until its nonce expires.
+pub struct Auth<T: Handler, S: CredentialStore> {
+	handler: T,
+	store: S,
+	realm: String,
+	basic: bool,
+	digest: Vec<DigestAlgorithm>,
+	secret: Vec<u8>,
+	nonce_lifetime: Duration,
+}
+
+impl<T: Handler, S: CredentialStore> Auth<T, S> {
+
+	// Starts with Basic authentication only. Panics if no random secret for
+	// Digest nonces can be read.
+	pub fn new(h: T, store: S, realm: &str) -> Auth<T, S> {
+		Auth {
+			handler: h,
+			store: store,
+			realm: String::from(realm),
+			basic: true,
+			digest: Vec::new(),
+			secret: crypto::random_bytes(32).expect("akasabi: cannot read random bytes for the Digest nonce secret"),
+			nonce_lifetime: Duration::from_secs(300),
+		}
+	}
+
+	// Basic sends passwords in the clear, so should only be used over HTTPS.
+	pub fn set_basic(&mut self, basic: bool) {
+		self.basic = basic;
+	}
+	// Offers Digest with an algorithm, in order of preference.
+	pub fn push_digest(&mut self, algorithm: DigestAlgorithm) {
+		self.digest.push(algorithm);
+	}
+	pub fn set_nonce_lifetime(&mut self, lifetime: Duration) {
+		self.nonce_lifetime = lifetime;
+	}
+
+	fn nonce(&self, time: u64) -> String {
+		let mut data = format!("{:016x}:", time).into_bytes();
+		data.extend_from_slice(&self.secret);
+		format!("{:016x}{}", time, crypto::encode_hex(&digest::sha256(&data)))
+	}
+
+	// Returns whether the nonce was issued by this server and is still fresh.
+	fn check_nonce(&self, nonce: &str) -> Result<(), Failure> {
+		let time = match nonce.get(.. 16).map(|t| u64::from_str_radix(t, 16)) {
+			Some(Ok(time)) => time,
+			_ => return Err(Failure::Missing),
+		};
+		if ! crypto::constant_time_eq(self.nonce(time).as_bytes(), nonce.as_bytes()) {
+			return Err(Failure::Missing)
+		}
+		if now().saturating_sub(time) > self.nonce_lifetime.as_secs() {
+			return Err(Failure::Stale)
+		}
+		Ok(())
+	}
+
+	fn basic(&self, credentials: &str) -> Result<String, Failure> {
+		let decoded = crypto::decode_base64(credentials.trim().as_bytes()).ok_or(Failure::Missing)?;
+		let decoded = from_utf8(&decoded).map_err(|_| Failure::Missing)?;
+		match decoded.find(':') {
+			Some(pos) if self.store.verify(&decoded[.. pos], &decoded[pos + 1 ..]) => Ok(String::from(&decoded[.. pos])),
+			_ => Err(Failure::Missing),
+		}
+	}
+
+	fn digest(&self, req: &Request, credentials: &str) -> Result<String, Failure> {
+		let params = parse_params(credentials);
+		let param = |name: &str| params.iter().find(|p| p.0 == name).map(|p| p.1.as_str());
+		let algorithm = match param("algorithm") {
+			None => DigestAlgorithm::Md5,
+			Some(a) => *self.digest.iter().find(|x| x.as_str().eq_ignore_ascii_case(a)).ok_or(Failure::Missing)?,
+		};
+		if ! self.digest.contains(&algorithm) || param("realm") != Some(self.realm.as_str()) {
+			return Err(Failure::Missing)
+		}
+		let (user, nonce, uri, response) = match (param("username"), param("nonce"), param("uri"), param("response")) {
+			(Some(user), Some(nonce), Some(uri), Some(response)) => (user, nonce, uri, response),
+			_ => return Err(Failure::Missing),
+		};
+		// The digest covers the URI, so it has to be the one requested.
+		if req.path() != Some(uri.as_bytes()) {
+			return Err(Failure::Missing)
+		}
+		let ha1 = self.store.digest_ha1(user, &self.realm, algorithm).ok_or(Failure::Missing)?;
+		let method = req.method().map_or("", |m| m.as_str());
+		let ha2 = algorithm.hash(format!("{}:{}", method, uri).as_bytes());
+		let expected = match param("qop") {
+			Some("auth") => match (param("nc"), param("cnonce")) {
+				(Some(nc), Some(cnonce)) => algorithm.hash(format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2).as_bytes()),
+				_ => return Err(Failure::Missing),
+			},
+			None => algorithm.hash(format!("{}:{}:{}", ha1, nonce, ha2).as_bytes()),
+			Some(_) => return Err(Failure::Missing),
+		};
+		if ! crypto::constant_time_eq(expected.as_bytes(), response.to_ascii_lowercase().as_bytes()) {
+			return Err(Failure::Missing)
+		}
+		// Only a correct response with an expired nonce is told it is stale,
+		// so the client can retry without asking the user again.
+		self.check_nonce(nonce)?;
+		Ok(String::from(user))
+	}
+
+	fn authenticate(&self, req: &Request) -> Result<String, Failure> {
+		let value = req.header().get_string(b"Authorization").and_then(|v| from_utf8(v).ok()).ok_or(Failure::Missing)?;
+		let (scheme, credentials) = match value.find(' ') {
+			Some(pos) => (&value[.. pos], &value[pos + 1 ..]),
+			None => return Err(Failure::Missing),
+		};
+		if self.basic && scheme.eq_ignore_ascii_case("Basic") {
+			self.basic(credentials)
+		} else if ! self.digest.is_empty() && scheme.eq_ignore_ascii_case("Digest") {
+			self.digest(req, credentials)
+		} else {
+			Err(Failure::Missing)
+		}
+	}
+
+	fn challenge(&self, failure: Failure) -> Response {
+		let mut response = Response::from_status(401);
+		let nonce = self.nonce(now());
+		for algorithm in &self.digest {
+			response.push_header("WWW-Authenticate", format!("Digest realm={}, qop=\"auth\", algorithm={}, nonce=\"{}\"{}",
+				quote(&self.realm), algorithm.as_str(), nonce,
+				if let Failure::Stale = failure { ", stale=true" } else { "" }).as_str());
+		}
+		if self.basic {
+			response.push_header("WWW-Authenticate", format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm)).as_str());
+		}
+		response
+	}
+}
+
+impl<T: Handler, S: CredentialStore> Handler for Auth<T, S> {
+	fn handle(&self, req: &Request) -> Response {
+		match self.authenticate(req) {
+			Ok(user) => {
+				let mut ext = ExtendedRequest::new(req);
+				ext.push_attr(USER_ATTR, &user);
+				self.handler.handle(&ext)
+			}
+			Err(failure) => self.challenge(failure),
+		}
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		match self.authenticate(req) {
+			Ok(user) => {
+				let mut ext = ExtendedRequest::new(req);
+				ext.push_attr(USER_ATTR, &user);
+				self.handler.expect_continue(&ext)
+			}
+			Err(failure) => Some(self.challenge(failure)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use super::Auth;
+	use super::CredentialStore;
+	use super::DigestAlgorithm;
+	use super::Htpasswd;
+	use super::MemoryStore;
+	use super::parse_params;
+	use crypto::encode_base64;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Whoami;
+	impl Handler for Whoami {
+		fn handle(&self, req: &Request) -> Response {
+			Response::from_str(req.user().unwrap_or("-"))
+		}
+	}
+	fn store() -> MemoryStore {
+		let mut store = MemoryStore::new();
+		store.insert("Mufasa", "Circle of Life");
+		store
+	}
+	fn content(response: Response) -> String {
+		String::from_utf8(response.content.unwrap()).unwrap()
+	}
+	#[test]
+	fn test_htpasswd() {
+		let htpasswd = Htpasswd::parse("# users\nbob:$2y$04$abcdefghijklmnopqrstuubyCG3zY1GIXMyxfivm.ClDiInHzxjiq\nalice:{SHA}qUqP5cyxm6YcTAhz05Hph5gvu9M=\ncarol:plain\n");
+		assert!(htpasswd.verify("bob", ""));
+		assert!(! htpasswd.verify("bob", "x"));
+		assert!(htpasswd.verify("alice", "test"));
+		assert!(! htpasswd.verify("alice", "Test"));
+		assert!(! htpasswd.verify("carol", "plain"));
+		assert!(! htpasswd.verify("dave", ""));
+	}
+	#[test]
+	fn test_basic() {
+		let auth = Auth::new(Whoami, store(), "admin \"area\"");
+		let response = with_request(&["GET / HTTP/1.1"], None, |req| auth.handle(req));
+		assert_eq!(401, response.status());
+		assert_eq!(Some("Basic realm=\"admin \\\"area\\\"\", charset=\"UTF-8\""), response.get_header("WWW-Authenticate"));
+		let header = format!("Authorization: basic {}", encode_base64(b"Mufasa:Circle of Life"));
+		assert_eq!("Mufasa", content(with_request(&["GET / HTTP/1.1", &header], None, |req| auth.handle(req))));
+		let header = format!("Authorization: Basic {}", encode_base64(b"Mufasa:circle of life"));
+		assert_eq!(401, with_request(&["GET / HTTP/1.1", &header], None, |req| auth.handle(req)).status());
+		assert_eq!(401, with_request(&["GET / HTTP/1.1", "Authorization: Basic !!!"], None, |req| auth.handle(req)).status());
+		let expect = with_request(&["POST / HTTP/1.1", "Expect: 100-continue"], None, |req| auth.expect_continue(req));
+		assert_eq!(Some(401), expect.map(|r| r.status()));
+	}
+	#[test]
+	fn test_parse_params() {
+		let params = parse_params("username=\"Mufasa\", realm=\"a \\\"b\\\", c\",nc=00000001 , qop=auth");
+		let expected: Vec<(String, String)> = vec![
+			(String::from("username"), String::from("Mufasa")),
+			(String::from("realm"), String::from("a \"b\", c")),
+			(String::from("nc"), String::from("00000001")),
+			(String::from("qop"), String::from("auth")),
+		];
+		assert_eq!(expected, params);
+	}
+	#[test]
+	fn test_digest() {
+		let mut auth = Auth::new(Whoami, store(), "testrealm@host.com");
+		auth.set_basic(false);
+		auth.push_digest(DigestAlgorithm::Sha256);
+		auth.push_digest(DigestAlgorithm::Md5);
+		let challenge = with_request(&["GET /dir/index.html HTTP/1.1"], None, |req| auth.handle(req));
+		assert_eq!(401, challenge.status());
+		let challenges: Vec<&str> = challenge.headers().iter().filter(|h| h.0 == "WWW-Authenticate").map(|h| h.1.as_str()).collect();
+		assert_eq!(2, challenges.len());
+		assert!(challenges[0].starts_with("Digest realm=\"testrealm@host.com\", qop=\"auth\", algorithm=SHA-256, nonce=\""));
+		let nonce = challenges[1].split("nonce=\"").nth(1).unwrap().trim_end_matches('"');
+		let sign = |algorithm: DigestAlgorithm, nonce: &str, method: &str, uri: &str| {
+			let ha1 = algorithm.hash(b"Mufasa:testrealm@host.com:Circle of Life");
+			let ha2 = algorithm.hash(format!("{}:{}", method, uri).as_bytes());
+			let response = algorithm.hash(format!("{}:{}:00000001:0a4f113b:auth:{}", ha1, nonce, ha2).as_bytes());
+			format!("Authorization: Digest username=\"Mufasa\", realm=\"testrealm@host.com\", nonce=\"{}\", uri=\"{}\", qop=auth, nc=00000001, cnonce=\"0a4f113b\", response=\"{}\", algorithm={}",
+				nonce, uri, response, algorithm.as_str())
+		};
+		let header = sign(DigestAlgorithm::Md5, nonce, "GET", "/dir/index.html");
+		assert_eq!("Mufasa", content(with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req))));
+		let header = sign(DigestAlgorithm::Sha256, nonce, "GET", "/dir/index.html");
+		assert_eq!("Mufasa", content(with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req))));
+		let header = sign(DigestAlgorithm::Md5, nonce, "POST", "/dir/index.html");
+		assert_eq!(401, with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req)).status());
+		let header = sign(DigestAlgorithm::Md5, nonce, "GET", "/other");
+		assert_eq!(401, with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req)).status());
+		let forged = format!("{}{}", &nonce[.. 16], "0".repeat(64));
+		let header = sign(DigestAlgorithm::Md5, &forged, "GET", "/dir/index.html");
+		let response = with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req));
+		assert_eq!(401, response.status());
+		assert!(! response.get_header("WWW-Authenticate").unwrap().contains("stale"));
+		let old = auth.nonce(1);
+		let header = sign(DigestAlgorithm::Md5, &old, "GET", "/dir/index.html");
+		let response = with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req));
+		assert_eq!(401, response.status());
+		assert!(response.get_header("WWW-Authenticate").unwrap().ends_with(", stale=true"));
+		auth.set_nonce_lifetime(Duration::from_secs(u64::MAX));
+		assert_eq!("Mufasa", content(with_request(&["GET /dir/index.html HTTP/1.1", &header], None, |req| auth.handle(req))));
+	}
+}
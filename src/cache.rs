@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use Handler;
+use Request;
+use Response;
+
+use http::Method;
+
+struct Entry {
+	response: Response,
+	path: Vec<u8>,
+	size: usize,
+	stored: Instant,
+	expires: Instant,
+	tick: u64,
+}
+
+// The entries, with their keys ordered by last use so the least recently
+// used one can be evicted first.
+struct Store {
+	entries: HashMap<Vec<u8>, Entry>,
+	order: BTreeMap<u64, Vec<u8>>,
+	size: usize,
+	tick: u64,
+}
+
+impl Store {
+	fn remove(&mut self, key: &[u8]) {
+		if let Some(entry) = self.entries.remove(key) {
+			self.order.remove(&entry.tick);
+			self.size -= entry.size;
+		}
+	}
+	fn get(&mut self, key: &[u8], now: Instant) -> Option<Response> {
+		let (tick, response) = match self.entries.get_mut(key) {
+			Some(ref entry) if entry.expires <= now => (None, None),
+			Some(entry) => {
+				self.tick += 1;
+				let tick = entry.tick;
+				entry.tick = self.tick;
+				let mut response = entry.response.clone();
+				response.set_header("Age", now.duration_since(entry.stored).as_secs().to_string().as_str());
+				(Some(tick), Some(response))
+			}
+			None => return None,
+		};
+		match tick {
+			Some(tick) => {
+				self.order.remove(&tick);
+				self.order.insert(self.tick, key.to_vec());
+			}
+			None => self.remove(key),
+		}
+		response
+	}
+	fn evict(&mut self) {
+		let key = match self.order.iter().next() {
+			Some((_, key)) => key.clone(),
+			None => return,
+		};
+		self.remove(&key);
+	}
+}
+
+fn response_size(response: &Response) -> usize {
+	let content = response.content.as_ref().map_or(0, |c| c.len());
+	response.headers.iter().fold(content, |a, h| a + h.0.len() + h.1.len())
+}
+
+// How long a response may be kept by its `Cache-Control`, or None if it
+// must not be stored. `s-maxage` is preferred to `max-age`, as this cache
+// is shared between clients.
+fn freshness(response: &Response, ttl: Duration) -> Option<Duration> {
+	let mut max_age = None;
+	let mut s_maxage = None;
+	if let Some(value) = response.get_header("Cache-Control") {
+		for directive in value.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+			match directive.as_str() {
+				"no-store" | "no-cache" | "private" => return None,
+				_ if directive.starts_with("max-age=") => max_age = directive[8 ..].parse().ok(),
+				_ if directive.starts_with("s-maxage=") => s_maxage = directive[9 ..].parse().ok(),
+				_ => {}
+			}
+		}
+	}
+	match s_maxage.or(max_age) {
+		Some(0) => None,
+		Some(secs) => Some(Duration::from_secs(secs)),
+		None => Some(ttl),
+	}
+}
+
+// Keeps successful GET responses in memory and serves them again without
+// calling the wrapped handler, until they expire or are evicted as the
+// least recently used. Requests are told apart by method, path with query
+// and the headers given to `push_vary`. Requests with `Authorization`, or
+// with `Cookie` unless it is one of those headers, are never cached.
+pub struct Cache<T: Handler> {
+	handler: T,
+	store: Mutex<Store>,
+	vary: Vec<String>,
+	max_entries: usize,
+	max_size: usize,
+	ttl: Duration,
+}
+
+impl<T: Handler> Cache<T> {
+
+	pub fn new(h: T) -> Cache<T> {
+		Cache {
+			handler: h,
+			store: Mutex::new(Store {
+				entries: HashMap::new(),
+				order: BTreeMap::new(),
+				size: 0,
+				tick: 0,
+			}),
+			vary: Vec::new(),
+			max_entries: 1000,
+			max_size: 16 * 1024 * 1024,
+			ttl: Duration::from_secs(60),
+		}
+	}
+
+	pub fn set_max_entries(&mut self, n: usize) {
+		self.max_entries = n;
+	}
+	// The total size of the responses kept, in bytes of content and headers.
+	pub fn set_max_size(&mut self, size: usize) {
+		self.max_size = size;
+	}
+	// How long to keep responses without a `max-age`.
+	pub fn set_ttl(&mut self, ttl: Duration) {
+		self.ttl = ttl;
+	}
+	// Adds a request header, such as `Accept-Language`, whose value selects
+	// a different response for the same path.
+	pub fn push_vary(&mut self, name: &str) {
+		self.vary.push(String::from(name));
+	}
+
+	// Drops every response whose path, with any query, starts with the
+	// prefix. This matches on the path only, so all variants of a path by
+	// the `push_vary` headers go together.
+	pub fn invalidate(&self, prefix: &str) {
+		if let Ok(mut store) = self.store.lock() {
+			let keys: Vec<Vec<u8>> = store.entries.iter()
+				.filter(|&(_, entry)| entry.path.starts_with(prefix.as_bytes()))
+				.map(|(key, _)| key.clone())
+				.collect();
+			for key in keys {
+				store.remove(&key);
+			}
+		}
+	}
+	pub fn clear(&self) {
+		self.invalidate("");
+	}
+
+	fn key(&self, req: &Request, method: Method, path: &[u8]) -> Vec<u8> {
+		let mut key = Vec::new();
+		key.extend_from_slice(method.as_str().as_bytes());
+		key.push(b' ');
+		key.extend_from_slice(path);
+		// A missing header is marked apart from an empty one.
+		for name in &self.vary {
+			match req.header().get_string(name.as_bytes()) {
+				Some(value) => {
+					key.extend_from_slice(b"\n=");
+					key.extend_from_slice(value);
+				}
+				None => key.extend_from_slice(b"\n-"),
+			}
+		}
+		key
+	}
+
+	fn store(&self, key: Vec<u8>, path: &[u8], response: &Response) {
+		// A cookie set for one client must not be handed to others.
+		if response.status() != 200 || response.get_header("Set-Cookie").is_some() {
+			return;
+		}
+		// Nor can responses be told apart that vary on headers, or `*`, left
+		// out of the key.
+		if let Some(vary) = response.get_header("Vary") {
+			if ! vary.split(',').map(|v| v.trim()).filter(|v| ! v.is_empty()).all(|v| self.vary.iter().any(|n| n.eq_ignore_ascii_case(v))) {
+				return;
+			}
+		}
+		let lifetime = match freshness(response, self.ttl) {
+			Some(lifetime) => lifetime,
+			None => return,
+		};
+		let size = response_size(response);
+		if size > self.max_size || self.max_entries == 0 {
+			return;
+		}
+		if let Ok(mut store) = self.store.lock() {
+			store.remove(&key);
+			while store.entries.len() >= self.max_entries || store.size + size > self.max_size {
+				store.evict();
+			}
+			let now = Instant::now();
+			store.tick += 1;
+			let tick = store.tick;
+			store.order.insert(tick, key.clone());
+			store.size += size;
+			store.entries.insert(key, Entry {
+				response: response.clone(),
+				path: path.to_vec(),
+				size: size,
+				stored: now,
+				expires: now + lifetime,
+				tick: tick,
+			});
+		}
+	}
+}
+
+impl<T: Handler> Handler for Cache<T> {
+	fn handle(&self, req: &Request) -> Response {
+		// Responses to authorized requests may be meant for one user only, as
+		// may those to requests with a session cookie unless keyed by it.
+		let header = req.header();
+		let cacheable = header.get_string(b"Authorization").is_none()
+			&& (header.get_string(b"Cookie").is_none() || self.vary.iter().any(|n| n.eq_ignore_ascii_case("Cookie")));
+		let (method, path) = match (req.method(), req.path()) {
+			(Some(Method::GET), Some(path)) if cacheable => (Method::GET, path),
+			_ => return self.handler.handle(req),
+		};
+		let key = self.key(req, method, path);
+		if let Some(response) = self.store.lock().ok().and_then(|mut store| store.get(&key, Instant::now())) {
+			return response;
+		}
+		let response = self.handler.handle(req);
+		self.store(key, path, &response);
+		response
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+	use std::time::Duration;
+	use super::Cache;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Counter {
+		calls: Cell<usize>,
+	}
+	impl Handler for Counter {
+		fn handle(&self, req: &Request) -> Response {
+			self.calls.set(self.calls.get() + 1);
+			let path = String::from_utf8(req.path().unwrap().to_vec()).unwrap();
+			let mut response = Response::from_string(format!("{} {}", path, self.calls.get()));
+			if path.starts_with("/private") {
+				response.set_header("Cache-Control", "private, max-age=60");
+			} else if path.starts_with("/short") {
+				response.set_header("Cache-Control", "max-age=1");
+			} else if path.starts_with("/session") {
+				response.push_header("Set-Cookie", "session=1");
+			} else if path.starts_with("/cookie") {
+				response.set_header("Vary", "accept-language, Cookie");
+			}
+			response
+		}
+	}
+	fn get(cache: &Cache<Counter>, lines: &[&str]) -> String {
+		let response = with_request(lines, None, |req| cache.handle(req));
+		String::from_utf8(response.content.unwrap()).unwrap()
+	}
+	#[test]
+	fn test_cache() {
+		let mut cache = Cache::new(Counter { calls: Cell::new(0) });
+		cache.push_vary("Accept-Language");
+		cache.set_max_entries(3);
+		assert_eq!("/a 1", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/a 1", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/a 2", get(&cache, &["GET /a HTTP/1.1", "Accept-Language: ja"]));
+		assert_eq!("/a?x 3", get(&cache, &["GET /a?x HTTP/1.1"]));
+		assert_eq!("/a 4", get(&cache, &["POST /a HTTP/1.1"]));
+		assert_eq!("/a 5", get(&cache, &["GET /a HTTP/1.1", "Authorization: Basic eDp5"]));
+		assert_eq!("/private 6", get(&cache, &["GET /private HTTP/1.1"]));
+		assert_eq!("/private 7", get(&cache, &["GET /private HTTP/1.1"]));
+		// The least recently used entry, `/a` in Japanese, is evicted.
+		assert_eq!("/a 1", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/b 8", get(&cache, &["GET /b HTTP/1.1"]));
+		assert_eq!("/a 1", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/a 9", get(&cache, &["GET /a HTTP/1.1", "Accept-Language: ja"]));
+		cache.invalidate("/a");
+		assert_eq!("/a 10", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/b 8", get(&cache, &["GET /b HTTP/1.1"]));
+	}
+	#[test]
+	fn test_uncacheable() {
+		let mut cache = Cache::new(Counter { calls: Cell::new(0) });
+		cache.push_vary("Accept-Language");
+		assert_eq!("/session 1", get(&cache, &["GET /session HTTP/1.1"]));
+		assert_eq!("/session 2", get(&cache, &["GET /session HTTP/1.1"]));
+		assert_eq!("/a 3", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/a 4", get(&cache, &["GET /a HTTP/1.1", "Accept-Language:"]));
+		assert_eq!("/a 3", get(&cache, &["GET /a HTTP/1.1"]));
+		assert_eq!("/a 4", get(&cache, &["GET /a HTTP/1.1", "Accept-Language:"]));
+		assert_eq!("/cookie 5", get(&cache, &["GET /cookie HTTP/1.1"]));
+		assert_eq!("/cookie 6", get(&cache, &["GET /cookie HTTP/1.1"]));
+		// A page for a cookie session is not shared, even without `Vary`.
+		assert_eq!("/b 7", get(&cache, &["GET /b HTTP/1.1", "Cookie: session=1"]));
+		assert_eq!("/b 8", get(&cache, &["GET /b HTTP/1.1", "Cookie: session=2"]));
+		cache.push_vary("Cookie");
+		assert_eq!("/cookie 9", get(&cache, &["GET /cookie HTTP/1.1"]));
+		assert_eq!("/cookie 9", get(&cache, &["GET /cookie HTTP/1.1"]));
+		assert_eq!("/b 10", get(&cache, &["GET /b HTTP/1.1", "Cookie: session=1"]));
+		assert_eq!("/b 10", get(&cache, &["GET /b HTTP/1.1", "Cookie: session=1"]));
+		assert_eq!("/b 11", get(&cache, &["GET /b HTTP/1.1", "Cookie: session=2"]));
+	}
+	#[test]
+	fn test_limits() {
+		let mut cache = Cache::new(Counter { calls: Cell::new(0) });
+		cache.set_max_size(100);
+		assert_eq!("/short 1", get(&cache, &["GET /short HTTP/1.1"]));
+		assert_eq!("/short 1", get(&cache, &["GET /short HTTP/1.1"]));
+		let response = with_request(&["GET /short HTTP/1.1"], None, |req| cache.handle(req));
+		assert_eq!(Some("0"), response.get_header("Age"));
+		assert_eq!("/a 2", get(&cache, &["GET /a HTTP/1.1"]));
+		{
+			// Entries are looked up at a given time, so expiry is checked
+			// without waiting for it.
+			let mut store = cache.store.lock().unwrap();
+			let entry = |store: &super::Store, path: &[u8]| store.entries.iter().find(|e| e.1.path == path).map(|e| (e.0.clone(), e.1.stored)).unwrap();
+			let (short, stored) = entry(&store, b"/short");
+			assert!(store.get(&short, stored + Duration::from_millis(999)).is_some());
+			assert!(store.get(&short, stored + Duration::from_secs(1)).is_none());
+			let (a, stored) = entry(&store, b"/a");
+			let response = store.get(&a, stored + Duration::from_secs(59)).unwrap();
+			assert_eq!(Some("59"), response.get_header("Age"));
+			assert!(store.get(&a, stored + Duration::from_secs(60)).is_none());
+			assert!(store.entries.is_empty());
+			assert_eq!(0, store.size);
+		}
+		assert_eq!("/short 3", get(&cache, &["GET /short HTTP/1.1"]));
+		let long = format!("/{}", "x".repeat(100));
+		let line = format!("GET {} HTTP/1.1", long);
+		assert_eq!(format!("{} 4", long), get(&cache, &[line.as_str()]));
+		assert_eq!(format!("{} 5", long), get(&cache, &[line.as_str()]));
+		cache.clear();
+		assert_eq!("/short 6", get(&cache, &["GET /short HTTP/1.1"]));
+	}
+}
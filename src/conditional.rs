@@ -0,0 +1,176 @@
+use Handler;
+use Request;
+use Response;
+
+use http;
+use http::Method;
+
+// Headers a 304 response keeps from the response it replaces.
+const NOT_MODIFIED_HEADERS: [&str; 6] = ["ETag", "Last-Modified", "Cache-Control", "Expires", "Vary", "Content-Location"];
+
+// Splits a list of entity tags such as `W/"a", "b"` into (weak, tag)
+// pairs, the tags keeping their quotes. Parsing stops at anything
+// malformed.
+fn parse_etags(value: &[u8]) -> Vec<(bool, &[u8])> {
+	let mut etags = Vec::new();
+	let mut rest = value;
+	loop {
+		while let Some((&x, tail)) = rest.split_first() {
+			if x != b' ' && x != b',' {
+				break;
+			}
+			rest = tail;
+		}
+		let weak = rest.starts_with(b"W/");
+		if weak {
+			rest = &rest[2 ..];
+		}
+		if rest.first() != Some(&b'"') {
+			break;
+		}
+		match rest[1 ..].iter().position(|&x| x == b'"') {
+			Some(pos) => {
+				etags.push((weak, &rest[.. pos + 2]));
+				rest = &rest[pos + 2 ..];
+			}
+			None => break,
+		}
+	}
+	etags
+}
+
+// Whether an `If-Match` or `If-None-Match` value matches the response's
+// entity tag. `*` matches any response, since only successful ones are
+// checked.
+fn matches(value: &[u8], etag: Option<(bool, &[u8])>, strong: bool) -> bool {
+	if value == b"*" {
+		return true
+	}
+	match etag {
+		Some((weak, tag)) => parse_etags(value).iter().any(|&(w, t)| t == tag && ! (strong && (weak || w))),
+		None => false,
+	}
+}
+
+// Evaluates the preconditions of RFC 7232 in its order, returning the
+// status to answer with instead of the response, if any.
+fn evaluate(req: &Request, response: &Response) -> Option<u32> {
+	let header = req.header();
+	let etag = response.get_header("ETag").and_then(|v| parse_etags(v.as_bytes()).into_iter().next());
+	let last_modified = response.get_header("Last-Modified").and_then(|v| http::parse_date(v.as_bytes()));
+	if let Some(value) = header.get_string(b"If-Match") {
+		if ! matches(value, etag, true) {
+			return Some(412)
+		}
+	} else if let (Some(since), Some(modified)) = (header.get_string(b"If-Unmodified-Since").and_then(http::parse_date), last_modified) {
+		if modified > since {
+			return Some(412)
+		}
+	}
+	let safe = match req.method() {
+		Some(Method::GET) => true,
+		_ => false,
+	};
+	if let Some(value) = header.get_string(b"If-None-Match") {
+		if matches(value, etag, false) {
+			return Some(if safe { 304 } else { 412 })
+		}
+	} else if let (true, Some(since), Some(modified)) = (safe, header.get_string(b"If-Modified-Since").and_then(http::parse_date), last_modified) {
+		if modified <= since {
+			return Some(304)
+		}
+	}
+	None
+}
+
+// Answers conditional requests against the `ETag` and `Last-Modified` of
+// the wrapped handler's responses, with 304 Not Modified or 412
+// Precondition Failed. The handler still runs, so this saves bandwidth
+// rather than work, and unsafe requests should be checked by the handler
+// itself before making changes.
+pub struct Conditional<T: Handler> {
+	handler: T,
+}
+
+impl<T: Handler> Conditional<T> {
+	pub fn new(h: T) -> Conditional<T> {
+		Conditional {
+			handler: h,
+		}
+	}
+}
+
+impl<T: Handler> Handler for Conditional<T> {
+	fn handle(&self, req: &Request) -> Response {
+		let response = self.handler.handle(req);
+		if response.status() < 200 || response.status() >= 300 {
+			return response
+		}
+		match evaluate(req, &response) {
+			Some(304) => {
+				let mut not_modified = Response::new(None);
+				not_modified.status = 304;
+				not_modified.headers = response.headers.into_iter()
+					.filter(|h| NOT_MODIFIED_HEADERS.iter().any(|name| h.0.eq_ignore_ascii_case(name)))
+					.collect();
+				not_modified
+			}
+			Some(status) => Response::from_status(status),
+			None => response,
+		}
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use std::time::UNIX_EPOCH;
+	use super::Conditional;
+	use super::parse_etags;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Page;
+	impl Handler for Page {
+		fn handle(&self, _: &Request) -> Response {
+			let mut response = Response::from_str("page");
+			response.set_etag("v2");
+			response.set_last_modified(UNIX_EPOCH + Duration::from_secs(784111777));
+			response.set_header("Cache-Control", "max-age=60");
+			response.set_header("X-Other", "1");
+			response
+		}
+	}
+	fn status(lines: &[&str]) -> u32 {
+		with_request(lines, None, |req| Conditional::new(Page).handle(req)).status()
+	}
+	#[test]
+	fn test_parse_etags() {
+		let etags: Vec<(bool, &[u8])> = vec![(true, b"\"a,b\""), (false, b"\"\"")];
+		assert_eq!(etags, parse_etags(b"W/\"a,b\" ,\"\", x"));
+	}
+	#[test]
+	fn test_conditional() {
+		assert_eq!(200, status(&["GET / HTTP/1.1"]));
+		let response = with_request(&["GET / HTTP/1.1", "If-None-Match: \"v1\", W/\"v2\""], None, |req| Conditional::new(Page).handle(req));
+		assert_eq!(304, response.status());
+		assert_eq!(None, response.content);
+		assert_eq!(Some("\"v2\""), response.get_header("ETag"));
+		assert_eq!(Some("max-age=60"), response.get_header("Cache-Control"));
+		assert_eq!(None, response.get_header("X-Other"));
+		assert_eq!(200, status(&["GET / HTTP/1.1", "If-None-Match: \"v1\""]));
+		assert_eq!(412, status(&["POST / HTTP/1.1", "If-None-Match: *"]));
+		assert_eq!(304, status(&["GET / HTTP/1.1", "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"]));
+		assert_eq!(200, status(&["GET / HTTP/1.1", "If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT"]));
+		assert_eq!(200, status(&["GET / HTTP/1.1", "If-None-Match: \"v1\"", "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"]));
+		assert_eq!(200, status(&["GET / HTTP/1.1", "If-Modified-Since: yesterday"]));
+		assert_eq!(200, status(&["POST / HTTP/1.1", "If-Match: \"v2\""]));
+		assert_eq!(412, status(&["POST / HTTP/1.1", "If-Match: W/\"v2\""]));
+		assert_eq!(412, status(&["POST / HTTP/1.1", "If-Unmodified-Since: Sunday, 06-Nov-94 08:49:36 GMT"]));
+		assert_eq!(200, status(&["POST / HTTP/1.1", "If-Unmodified-Since: Sun Nov  6 08:49:37 1994"]));
+	}
+}
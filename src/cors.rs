@@ -0,0 +1,260 @@
+use std::str::from_utf8;
+use std::time::Duration;
+
+use Handler;
+use Request;
+use Response;
+
+use http::Method;
+
+// Matches an allowed origin, which may have one `*` standing for any
+// subdomains, as in `https://*.example.com`.
+fn match_origin(pattern: &str, origin: &str) -> bool {
+	match pattern.find('*') {
+		Some(pos) => {
+			let (prefix, suffix) = (&pattern[.. pos], &pattern[pos + 1 ..]);
+			origin.len() > prefix.len() + suffix.len() && origin.starts_with(prefix) && origin.ends_with(suffix)
+				&& ! origin[prefix.len() .. origin.len() - suffix.len()].contains(&['/', ':', '@'][..])
+		}
+		None => pattern == origin,
+	}
+}
+
+fn push_vary(response: &mut Response, names: &str) {
+	let vary = match response.get_header("Vary") {
+		Some(vary) if vary.trim() == "*" => return,
+		Some(vary) => format!("{}, {}", vary, names),
+		None => String::from(names),
+	};
+	response.set_header("Vary", &vary);
+}
+
+type OriginPredicate = Box<Fn(&str) -> bool + Send + Sync>;
+
+// Lets pages from other origins call the wrapped handler, answering
+// preflight `OPTIONS` requests itself and adding `Access-Control-*`
+// headers to the handler's responses for allowed origins.
+pub struct Cors<T: Handler> {
+	handler: T,
+	any_origin: bool,
+	origins: Vec<String>,
+	predicate: Option<OriginPredicate>,
+	methods: Vec<Method>,
+	any_header: bool,
+	headers: Vec<String>,
+	exposed_headers: Vec<String>,
+	credentials: bool,
+	max_age: Option<Duration>,
+}
+
+impl<T: Handler> Cors<T> {
+
+	// Starts allowing no origins, and GET and POST from those added.
+	pub fn new(h: T) -> Cors<T> {
+		Cors {
+			handler: h,
+			any_origin: false,
+			origins: Vec::new(),
+			predicate: None,
+			methods: vec![Method::GET, Method::POST],
+			any_header: false,
+			headers: Vec::new(),
+			exposed_headers: Vec::new(),
+			credentials: false,
+			max_age: None,
+		}
+	}
+
+	// Allows an origin such as `https://app.example.com`, or a pattern such
+	// as `https://*.example.com`.
+	pub fn push_origin(&mut self, origin: &str) {
+		self.origins.push(origin.trim_end_matches('/').to_ascii_lowercase());
+	}
+	// Answers every origin with `*`. Panics if credentials are allowed.
+	pub fn set_any_origin(&mut self, any: bool) {
+		assert!(! (any && self.credentials), "akasabi: CORS credentials cannot be allowed for any origin");
+		self.any_origin = any;
+	}
+	// Allows origins for which the predicate returns true, besides those
+	// added with `push_origin`.
+	pub fn set_origin_predicate<F: Fn(&str) -> bool + Send + Sync + 'static>(&mut self, predicate: F) {
+		self.predicate = Some(Box::new(predicate));
+	}
+	pub fn set_methods(&mut self, methods: &[Method]) {
+		self.methods = methods.to_vec();
+	}
+	// Allows a request header that browsers ask about in preflights, such as
+	// `Content-Type` for JSON or `Authorization`.
+	pub fn push_header(&mut self, name: &str) {
+		self.headers.push(name.to_ascii_lowercase());
+	}
+	pub fn set_any_header(&mut self, any: bool) {
+		self.any_header = any;
+	}
+	// Lets scripts read a response header beyond the simple ones.
+	pub fn push_exposed_header(&mut self, name: &str) {
+		self.exposed_headers.push(String::from(name));
+	}
+	// Allows cookies and authorization from the origins added or matched
+	// by the predicate. Panics if any origin is allowed, as every site could
+	// then read responses meant for the user.
+	pub fn set_credentials(&mut self, credentials: bool) {
+		assert!(! (credentials && self.any_origin), "akasabi: CORS credentials cannot be allowed for any origin");
+		self.credentials = credentials;
+	}
+	// How long browsers may cache a preflight result.
+	pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+		self.max_age = max_age;
+	}
+
+	fn is_allowed(&self, origin: &str) -> bool {
+		let lower = origin.to_ascii_lowercase();
+		self.any_origin || self.origins.iter().any(|o| match_origin(o, &lower)) || self.predicate.as_ref().is_some_and(|p| p(origin))
+	}
+
+	fn allow_origin(&self, response: &mut Response, origin: &str) {
+		if self.any_origin {
+			response.set_header("Access-Control-Allow-Origin", "*");
+		} else {
+			response.set_header("Access-Control-Allow-Origin", origin);
+		}
+		if self.credentials {
+			response.set_header("Access-Control-Allow-Credentials", "true");
+		}
+	}
+
+	fn preflight(&self, origin: &str, method: &[u8], headers: Option<&[u8]>) -> Response {
+		let requested: Vec<String> = headers.and_then(|h| from_utf8(h).ok()).unwrap_or("")
+			.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| ! h.is_empty()).collect();
+		let allowed = self.is_allowed(origin)
+			&& self.methods.iter().any(|m| m.as_str().as_bytes() == method)
+			&& (self.any_header || requested.iter().all(|h| self.headers.contains(h)));
+		if ! allowed {
+			return Response::from_status(403)
+		}
+		let mut response = Response::new(None);
+		response.set_status(204);
+		self.allow_origin(&mut response, origin);
+		let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+		response.set_header("Access-Control-Allow-Methods", &methods.join(", "));
+		if ! requested.is_empty() {
+			response.set_header("Access-Control-Allow-Headers", &requested.join(", "));
+		}
+		if let Some(max_age) = self.max_age {
+			response.set_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+		}
+		push_vary(&mut response, "Origin, Access-Control-Request-Method, Access-Control-Request-Headers");
+		response
+	}
+}
+
+impl<T: Handler> Handler for Cors<T> {
+	fn handle(&self, req: &Request) -> Response {
+		let header = req.header();
+		let origin = header.get_string(b"Origin").and_then(|o| from_utf8(o).ok());
+		if let (Some(Method::OPTIONS), Some(origin), Some(method)) = (req.method(), origin, header.get_string(b"Access-Control-Request-Method")) {
+			return self.preflight(origin, method, header.get_string(b"Access-Control-Request-Headers"))
+		}
+		let mut response = self.handler.handle(req);
+		if let Some(origin) = origin {
+			if self.is_allowed(origin) {
+				self.allow_origin(&mut response, origin);
+				if ! self.exposed_headers.is_empty() {
+					response.set_header("Access-Control-Expose-Headers", &self.exposed_headers.join(", "));
+				}
+			}
+		}
+		// Caches must keep responses for different origins apart, unless
+		// every origin gets the same `*`.
+		if ! self.any_origin {
+			push_vary(&mut response, "Origin");
+		}
+		response
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use super::Cors;
+	use super::match_origin;
+	use http::Method;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Api;
+	impl Handler for Api {
+		fn handle(&self, _: &Request) -> Response {
+			let mut response = Response::from_str("{}");
+			response.set_header("Vary", "Accept");
+			response
+		}
+	}
+	#[test]
+	fn test_match_origin() {
+		assert!(match_origin("https://*.example.com", "https://a.b.example.com"));
+		assert!(! match_origin("https://*.example.com", "https://example.com"));
+		assert!(! match_origin("https://*.example.com", "https://evil.com/.example.com"));
+		assert!(! match_origin("https://*.example.com", "http://a.example.com"));
+		assert!(match_origin("https://example.com", "https://example.com"));
+	}
+	#[test]
+	fn test_preflight() {
+		let mut cors = Cors::new(Api);
+		cors.push_origin("https://app.example.com/");
+		cors.set_methods(&[Method::GET, Method::POST, Method::OPTIONS]);
+		cors.push_header("Content-Type");
+		cors.set_max_age(Some(Duration::from_secs(600)));
+		let preflight = |lines: &[&str]| with_request(lines, None, |req| cors.handle(req));
+		let response = preflight(&["OPTIONS /api HTTP/1.1", "Origin: https://App.example.com", "Access-Control-Request-Method: POST", "Access-Control-Request-Headers: content-type"]);
+		assert_eq!(204, response.status());
+		assert_eq!(None, response.content);
+		assert_eq!(Some("https://App.example.com"), response.get_header("Access-Control-Allow-Origin"));
+		assert_eq!(Some("GET, POST, OPTIONS"), response.get_header("Access-Control-Allow-Methods"));
+		assert_eq!(Some("content-type"), response.get_header("Access-Control-Allow-Headers"));
+		assert_eq!(Some("600"), response.get_header("Access-Control-Max-Age"));
+		assert_eq!(None, response.get_header("Access-Control-Allow-Credentials"));
+		assert_eq!(403, preflight(&["OPTIONS /api HTTP/1.1", "Origin: https://evil.com", "Access-Control-Request-Method: POST"]).status());
+		assert_eq!(403, preflight(&["OPTIONS /api HTTP/1.1", "Origin: https://app.example.com", "Access-Control-Request-Method: DELETE"]).status());
+		assert_eq!(403, preflight(&["OPTIONS /api HTTP/1.1", "Origin: https://app.example.com", "Access-Control-Request-Method: POST", "Access-Control-Request-Headers: X-Secret"]).status());
+		assert_eq!(Some(b"{}".to_vec()), preflight(&["OPTIONS /api HTTP/1.1", "Origin: https://app.example.com"]).content);
+	}
+	#[test]
+	fn test_simple() {
+		let mut cors = Cors::new(Api);
+		cors.set_origin_predicate(|o| o.ends_with(".test"));
+		cors.set_credentials(true);
+		cors.push_exposed_header("X-Total");
+		let response = with_request(&["GET /api HTTP/1.1", "Origin: http://a.test"], None, |req| cors.handle(req));
+		assert_eq!(Some("http://a.test"), response.get_header("Access-Control-Allow-Origin"));
+		assert_eq!(Some("true"), response.get_header("Access-Control-Allow-Credentials"));
+		assert_eq!(Some("X-Total"), response.get_header("Access-Control-Expose-Headers"));
+		assert_eq!(Some("Accept, Origin"), response.get_header("Vary"));
+		let response = with_request(&["GET /api HTTP/1.1", "Origin: http://a.com"], None, |req| cors.handle(req));
+		assert_eq!(None, response.get_header("Access-Control-Allow-Origin"));
+		assert_eq!(Some("Accept, Origin"), response.get_header("Vary"));
+		let mut cors = Cors::new(Api);
+		cors.set_any_origin(true);
+		let response = with_request(&["GET /api HTTP/1.1", "Origin: http://a.com"], None, |req| cors.handle(req));
+		assert_eq!(Some("*"), response.get_header("Access-Control-Allow-Origin"));
+		assert_eq!(Some("Accept"), response.get_header("Vary"));
+	}
+	#[test]
+	#[should_panic(expected = "credentials cannot be allowed for any origin")]
+	fn test_any_origin_with_credentials() {
+		let mut cors = Cors::new(Api);
+		cors.set_credentials(true);
+		cors.set_any_origin(true);
+	}
+	#[test]
+	#[should_panic(expected = "credentials cannot be allowed for any origin")]
+	fn test_credentials_with_any_origin() {
+		let mut cors = Cors::new(Api);
+		cors.set_any_origin(true);
+		cors.set_credentials(true);
+	}
+}
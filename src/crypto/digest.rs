@@ -0,0 +1,192 @@
+// MD5, SHA-1 and SHA-256, for Digest authentication and htpasswd files.
+// MD5 and SHA-1 are broken for collisions and only kept for protocols
+// that still need them.
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+const MD5_K: [u32; 64] = [
+	0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+	0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+	0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+	0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+	0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+	0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+	0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+	0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+const SHA256_K: [u32; 64] = [
+	0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+	0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+	0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+	0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+	0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+	0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+	0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+	0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// Splits the message into 64 byte blocks after Merkle-Damgård padding,
+// with the bit length in the given byte order.
+fn blocks<F: FnMut(&[u8])>(data: &[u8], big_endian: bool, mut f: F) {
+	let bits = (data.len() as u64).wrapping_mul(8);
+	let mut tail = data[data.len() / 64 * 64 ..].to_vec();
+	tail.push(0x80);
+	while tail.len() % 64 != 56 {
+		tail.push(0);
+	}
+	tail.extend_from_slice(&if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() });
+	for block in data[.. data.len() / 64 * 64].chunks(64).chain(tail.chunks(64)) {
+		f(block);
+	}
+}
+
+pub fn md5(data: &[u8]) -> [u8; 16] {
+	let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+	blocks(data, false, |block| {
+		let mut m = [0u32; 16];
+		for (i, word) in block.chunks(4).enumerate() {
+			m[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+		}
+		let (mut a, mut b, mut c, mut d) = (h[0], h[1], h[2], h[3]);
+		for i in 0 .. 64 {
+			let (f, g) = match i / 16 {
+				0 => ((b & c) | (! b & d), i),
+				1 => ((d & b) | (! d & c), (5 * i + 1) % 16),
+				2 => (b ^ c ^ d, (3 * i + 5) % 16),
+				_ => (c ^ (b | ! d), (7 * i) % 16),
+			};
+			let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+			a = d;
+			d = c;
+			c = b;
+			b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+		}
+		h[0] = h[0].wrapping_add(a);
+		h[1] = h[1].wrapping_add(b);
+		h[2] = h[2].wrapping_add(c);
+		h[3] = h[3].wrapping_add(d);
+	});
+	let mut out = [0; 16];
+	for (i, x) in h.iter().enumerate() {
+		out[i * 4 .. i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+	}
+	out
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+	let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+	blocks(data, true, |block| {
+		let mut w = [0u32; 80];
+		for (i, word) in block.chunks(4).enumerate() {
+			w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+		}
+		for i in 16 .. 80 {
+			w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+		}
+		let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+		for (i, &x) in w.iter().enumerate() {
+			let (f, k) = match i / 20 {
+				0 => ((b & c) | (! b & d), 0x5a827999),
+				1 => (b ^ c ^ d, 0x6ed9eba1),
+				2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+				_ => (b ^ c ^ d, 0xca62c1d6),
+			};
+			let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(x);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = t;
+		}
+		h[0] = h[0].wrapping_add(a);
+		h[1] = h[1].wrapping_add(b);
+		h[2] = h[2].wrapping_add(c);
+		h[3] = h[3].wrapping_add(d);
+		h[4] = h[4].wrapping_add(e);
+	});
+	let mut out = [0; 20];
+	for (i, x) in h.iter().enumerate() {
+		out[i * 4 .. i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+	}
+	out
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+	let mut h: [u32; 8] = [
+		0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+	];
+	blocks(data, true, |block| {
+		let mut w = [0u32; 64];
+		for (i, word) in block.chunks(4).enumerate() {
+			w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+		}
+		for i in 16 .. 64 {
+			let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+			let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+			w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+		}
+		let mut v = h;
+		for i in 0 .. 64 {
+			let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+			let ch = (v[4] & v[5]) ^ (! v[4] & v[6]);
+			let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+			let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+			let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+			let t2 = s0.wrapping_add(maj);
+			v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+		}
+		for (x, y) in h.iter_mut().zip(v.iter()) {
+			*x = x.wrapping_add(*y);
+		}
+	});
+	let mut out = [0; 32];
+	for (i, x) in h.iter().enumerate() {
+		out[i * 4 .. i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+	}
+	out
+}
+
+// HMAC from RFC 2104 over SHA-256, for signing tokens.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+	let mut block = [0u8; 64];
+	if key.len() > 64 {
+		block[.. 32].copy_from_slice(&sha256(key));
+	} else {
+		block[.. key.len()].copy_from_slice(key);
+	}
+	let mut inner: Vec<u8> = block.iter().map(|x| x ^ 0x36).collect();
+	inner.extend_from_slice(data);
+	let mut outer: Vec<u8> = block.iter().map(|x| x ^ 0x5C).collect();
+	outer.extend_from_slice(&sha256(&inner));
+	sha256(&outer)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::hmac_sha256;
+	use super::md5;
+	use super::sha1;
+	use super::sha256;
+	use crypto::encode_hex;
+	#[test]
+	fn test_digests() {
+		let long = vec![b'a'; 1000];
+		let cases: [(&[u8], &str, &str, &str); 4] = [
+			(b"", "d41d8cd98f00b204e9800998ecf8427e", "da39a3ee5e6b4b0d3255bfef95601890afd80709", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+			(b"abc", "900150983cd24fb0d6963f7d28e17f72", "a9993e364706816aba3e25717850c26c9cd0d89d", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+			(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "8215ef0796a20bcaaae116d3876c664a", "84983e441c3bd26ebaae4aa1f95129e5e54670f1", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+			(&long, "cabe45dcc9ae5b66ba86600cca6b8ba8", "291e9a6c66994949b57ba5e650361e98fc36b1ba", "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"),
+		];
+		for &(data, m, s1, s256) in &cases {
+			assert_eq!(m, encode_hex(&md5(data)));
+			assert_eq!(s1, encode_hex(&sha1(data)));
+			assert_eq!(s256, encode_hex(&sha256(data)));
+		}
+	}
+	#[test]
+	fn test_hmac_sha256() {
+		assert_eq!("f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8", encode_hex(&hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog")));
+		assert_eq!("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54", encode_hex(&hmac_sha256(&[0xAA; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")));
+	}
+}
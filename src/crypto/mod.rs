@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+pub mod digest;
+pub mod bcrypt;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// Reads from /dev/urandom. There is no fallback, as these bytes are used
+// for secrets that must not be guessable.
+pub fn random_bytes(n: usize) -> io::Result<Vec<u8>> {
+	let mut buf: Vec<u8> = vec![0; n];
+	File::open("/dev/urandom")?.read_exact(buf.as_mut_slice())?;
+	Ok(buf)
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+	let digits = b"0123456789abcdef";
+	let mut result = String::with_capacity(bytes.len() * 2);
+	for &x in bytes {
+		result.push(digits[(x >> 4) as usize] as char);
+		result.push(digits[(x & 0x0F) as usize] as char);
+	}
+	result
+}
+
+fn encode_base64_with(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+	let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let n = chunk.iter().enumerate().fold(0u32, |a, (i, &x)| a | (x as u32) << (16 - 8 * i));
+		for i in 0 .. chunk.len() + 1 {
+			result.push(alphabet[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+		}
+		if pad {
+			for _ in chunk.len() .. 3 {
+				result.push('=');
+			}
+		}
+	}
+	result
+}
+
+// Padding is optional, and bits left over in the last character ignored.
+fn decode_base64_with(s: &[u8], alphabet: &[u8; 64]) -> Option<Vec<u8>> {
+	let end = s.iter().rposition(|&x| x != b'=').map_or(0, |pos| pos + 1);
+	if s.len() - end > 2 {
+		return None
+	}
+	let mut result = Vec::with_capacity(end / 4 * 3 + 2);
+	for chunk in s[.. end].chunks(4) {
+		if chunk.len() == 1 {
+			return None
+		}
+		let mut n = 0u32;
+		for (i, x) in chunk.iter().enumerate() {
+			n |= (alphabet.iter().position(|a| a == x)? as u32) << (18 - 6 * i);
+		}
+		for i in 0 .. chunk.len() - 1 {
+			result.push((n >> (16 - 8 * i)) as u8);
+		}
+	}
+	Some(result)
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+	encode_base64_with(bytes, BASE64, true)
+}
+
+pub fn decode_base64(s: &[u8]) -> Option<Vec<u8>> {
+	decode_base64_with(s, BASE64)
+}
+
+// The URL and filename safe alphabet, without padding as in JWTs.
+pub fn encode_base64_url(bytes: &[u8]) -> String {
+	encode_base64_with(bytes, BASE64_URL, false)
+}
+
+pub fn decode_base64_url(s: &[u8]) -> Option<Vec<u8>> {
+	decode_base64_with(s, BASE64_URL)
+}
+
+// Compares without returning early, so timing does not reveal how much
+// of a secret matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::random_bytes;
+	use super::encode_hex;
+	use super::constant_time_eq;
+	use super::decode_base64;
+	use super::encode_base64;
+	use super::decode_base64_url;
+	use super::encode_base64_url;
+	#[test]
+	fn test_random_bytes() {
+		assert_eq!(16, random_bytes(16).unwrap().len());
+		assert!(random_bytes(16).unwrap() != random_bytes(16).unwrap());
+	}
+	#[test]
+	fn test_encode_hex() {
+		assert_eq!("00ff1a", encode_hex(&[0x00, 0xFF, 0x1A]));
+	}
+	#[test]
+	fn test_constant_time_eq() {
+		assert!(constant_time_eq(b"abc", b"abc"));
+		assert!(! constant_time_eq(b"abc", b"abd"));
+		assert!(! constant_time_eq(b"abc", b"ab"));
+	}
+	#[test]
+	fn test_base64() {
+		let cases: [(&[u8], &str); 7] = [
+			(b"", ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"),
+			(b"foob", "Zm9vYg=="), (b"fooba", "Zm9vYmE="), (b"\xfb\xff\xbf", "+/+/"),
+		];
+		for &(bytes, text) in &cases {
+			assert_eq!(text, encode_base64(bytes));
+			assert_eq!(Some(bytes.to_vec()), decode_base64(text.as_bytes()));
+		}
+		assert_eq!(Some(b"fo".to_vec()), decode_base64(b"Zm8"));
+		assert_eq!(None, decode_base64(b"Zm9vY"));
+		assert_eq!(None, decode_base64(b"Zm-v"));
+		assert_eq!(None, decode_base64(b"Zg==="));
+		assert_eq!("-_-_-2Y", encode_base64_url(b"\xfb\xff\xbf\xfbf"));
+		assert_eq!(Some(b"\xfb\xff\xbf\xfbf".to_vec()), decode_base64_url(b"-_-_-2Y"));
+		assert_eq!(None, decode_base64_url(b"+/+/"));
+	}
+}
@@ -0,0 +1,201 @@
+use ExtendedRequest;
+use Handler;
+use Request;
+use Response;
+
+use crypto;
+use html::builder::Tag;
+use http::Method;
+use url::Uri;
+
+pub const TOKEN_ATTR: &str = "csrf_token";
+
+fn is_safe_method(method: Option<Method>) -> bool {
+	match method {
+		Some(Method::GET) | Some(Method::OPTIONS) => true,
+		_ => false,
+	}
+}
+
+fn is_token(token: &[u8]) -> bool {
+	token.len() == 64 && token.iter().all(|x| x.is_ascii_hexdigit())
+}
+
+// Returns the token for the current request, set by `Csrf`.
+pub fn token(req: &Request) -> Option<&str> {
+	req.attr(TOKEN_ATTR)
+}
+
+// A hidden input carrying the token, to be pushed into a form.
+pub fn input(req: &Request) -> Tag<'static> {
+	Tag::new("input")
+		.attr("type", "hidden")
+		.attr("name", TOKEN_ATTR)
+		.attr("value", String::from(token(req).unwrap_or("")))
+}
+
+// Guards unsafe requests using a token kept in a cookie (the double submit
+// pattern) together with Origin and Referer checks.
+pub struct Csrf<T> {
+	handler: T,
+	cookie_name: String,
+	field_name: String,
+	trusted_origins: Vec<String>,
+	secure: bool,
+}
+
+impl<T: Handler> Csrf<T> {
+
+	pub fn new(h: T) -> Csrf<T> {
+		Csrf {
+			handler: h,
+			cookie_name: String::from("csrf_token"),
+			field_name: String::from(TOKEN_ATTR),
+			trusted_origins: Vec::new(),
+			secure: false,
+		}
+	}
+
+	pub fn set_cookie_name(&mut self, name: &str) {
+		self.cookie_name = String::from(name);
+	}
+	pub fn set_field_name(&mut self, name: &str) {
+		self.field_name = String::from(name);
+	}
+	// Adds an origin such as `https://example.com` that may post besides
+	// the request's own host.
+	pub fn push_trusted_origin(&mut self, origin: &str) {
+		self.trusted_origins.push(origin.trim_end_matches('/').to_ascii_lowercase());
+	}
+	// Marks the cookie `Secure`, for sites served only over HTTPS.
+	pub fn set_secure(&mut self, secure: bool) {
+		self.secure = secure;
+	}
+
+	// Checks the origin of an `Origin` or `Referer` value against the
+	// trusted origins and the request's own host.
+	fn check_origin(&self, req: &Request, value: &[u8]) -> bool {
+		let uri = match Uri::parse(value) {
+			Some(uri) => uri,
+			None => return false,
+		};
+		let authority = match (uri.host(), uri.port()) {
+			(Some(host), Some(port)) => format!("{}:{}", host, port),
+			(Some(host), None) => String::from(host),
+			_ => return false,
+		};
+		let origin = format!("{}://{}", uri.scheme().unwrap_or(""), authority).to_ascii_lowercase();
+		if self.trusted_origins.contains(&origin) {
+			return true;
+		}
+		match req.host() {
+			Some(host) => host.eq_ignore_ascii_case(authority.as_bytes()),
+			None => false,
+		}
+	}
+
+	fn verify(&self, req: &Request, cookie: Option<&[u8]>) -> bool {
+		let header = req.header();
+		if let Some(origin) = header.get_string(b"Origin").or_else(|| header.get_string(b"Referer")) {
+			if ! self.check_origin(req, origin) {
+				return false;
+			}
+		}
+		let cookie = match cookie {
+			Some(cookie) => cookie,
+			None => return false,
+		};
+		let submitted = match header.get_string(b"X-CSRF-Token") {
+			Some(token) => token.to_vec(),
+			None => match req.post_params().find(|p| p.name() == self.field_name) {
+				Some(param) => param.value().into_bytes(),
+				None => return false,
+			},
+		};
+		crypto::constant_time_eq(cookie, submitted.as_slice())
+	}
+}
+
+impl<T: Handler> Handler for Csrf<T> {
+	fn handle(&self, req: &Request) -> Response {
+		let cookie = req.header().cookie(self.cookie_name.as_bytes()).filter(|c| is_token(c));
+		if ! is_safe_method(req.method()) && ! self.verify(req, cookie) {
+			return Response::from_status(403);
+		}
+		let token = match cookie {
+			Some(cookie) => String::from_utf8(cookie.to_vec()).unwrap(),
+			None => match crypto::random_bytes(32) {
+				Ok(bytes) => crypto::encode_hex(bytes.as_slice()),
+				Err(_) => return Response::from_status(500),
+			},
+		};
+		let mut extended = ExtendedRequest::new(req);
+		extended.push_attr(TOKEN_ATTR, token.as_str());
+		let mut response = self.handler.handle(&extended);
+		// The token in the page belongs to the cookie it came with.
+		let vary = match response.get_header("Vary") {
+			Some(vary) => format!("{}, Cookie", vary),
+			None => String::from("Cookie"),
+		};
+		response.set_header("Vary", vary.as_str());
+		if cookie.is_none() {
+			response.push_header("Set-Cookie", format!("{}={}; Path=/; HttpOnly; SameSite=Strict{}",
+				self.cookie_name, token, if self.secure { "; Secure" } else { "" }).as_str());
+		}
+		response
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use Handler;
+	use Request;
+	use Response;
+	use http::with_request;
+	use super::Csrf;
+	use super::token;
+	struct Echo;
+	impl Handler for Echo {
+		fn handle(&self, req: &Request) -> Response {
+			Response::from_string(String::from(token(req).unwrap()))
+		}
+	}
+	const TOKEN: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+	#[test]
+	fn test_issue_token() {
+		let csrf = Csrf::new(Echo);
+		let response = with_request(&["GET / HTTP/1.1", "Host: example.com"], None, |req| csrf.handle(req));
+		let token = String::from_utf8(response.content.clone().unwrap()).unwrap();
+		assert_eq!(64, token.len());
+		assert_eq!(Some(format!("csrf_token={}; Path=/; HttpOnly; SameSite=Strict", token).as_str()), response.get_header("Set-Cookie"));
+		let cookie = format!("Cookie: a=b; csrf_token={}", TOKEN);
+		let response = with_request(&["GET / HTTP/1.1", cookie.as_str()], None, |req| csrf.handle(req));
+		assert_eq!(Some(TOKEN.as_bytes().to_vec()), response.content);
+		assert_eq!(None, response.get_header("Set-Cookie"));
+		assert_eq!(Some("Cookie"), response.get_header("Vary"));
+	}
+	#[test]
+	fn test_verify() {
+		let csrf = Csrf::new(Echo);
+		let cookie = format!("Cookie: csrf_token={}", TOKEN);
+		let body = format!("a=1&csrf_token={}", TOKEN);
+		let post = |lines: &[&str], body: &str| with_request(lines, Some(body.as_bytes()), |req| csrf.handle(req).status());
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com"], body.as_str()));
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str()], "a=1"));
+		assert_eq!(200, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str()], body.as_str()));
+		assert_eq!(200, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Origin: http://example.com"], body.as_str()));
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Origin: http://evil.com"], body.as_str()));
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Referer: http://evil.com/a"], body.as_str()));
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Referer: /"], body.as_str()));
+		assert_eq!(403, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Referer: x/"], body.as_str()));
+		assert_eq!(200, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Referer: http://example.com"], body.as_str()));
+		let mut csrf = Csrf::new(Echo);
+		csrf.push_trusted_origin("https://App.example.com/");
+		assert_eq!(200, with_request(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), "Referer: https://app.example.com/form"], Some(body.as_bytes()), |req| csrf.handle(req).status()));
+		let header = format!("X-CSRF-Token: {}", TOKEN);
+		assert_eq!(200, post(&["POST / HTTP/1.1", "Host: example.com", cookie.as_str(), header.as_str(), "Referer: http://example.com/a"], ""));
+	}
+}
@@ -1,117 +1,558 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+use Request;
+
 use html;
+use html::SafeHtml;
+
+const VOID_ELEMENTS: [&str; 14] = [
+	"area", "base", "br", "col", "embed", "hr", "img",
+	"input", "keygen", "link", "meta", "source", "track", "wbr",
+];
+
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+pub fn is_void_element(name: &str) -> bool {
+	VOID_ELEMENTS.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+pub fn is_raw_text_element(name: &str) -> bool {
+	RAW_TEXT_ELEMENTS.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+pub fn is_valid_tag_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() => chars.all(|c| c.is_ascii_alphanumeric() || c == '-'),
+		_ => false,
+	}
+}
+
+pub fn is_valid_attr_name(name: &str) -> bool {
+	! name.is_empty() && name.chars().all(|c| match c {
+		' ' | '"' | '\'' | '>' | '/' | '=' | '<' => false,
+		_ => ! c.is_whitespace() && ! c.is_control(),
+	})
+}
+
+// Writes the content of a raw text element, breaking up anything that
+// would close the element early.
+fn write_raw_text<W: fmt::Write + ?Sized>(w: &mut W, name: &str, s: &str) -> fmt::Result {
+	let mut last = 0;
+	let bytes = s.as_bytes();
+	let mut i = 0;
+	while i + 2 + name.len() <= bytes.len() {
+		if bytes[i] == b'<' && bytes[i + 1] == b'/' && bytes[i + 2 .. i + 2 + name.len()].eq_ignore_ascii_case(name.as_bytes()) {
+			w.write_str(&s[last .. i + 1])?;
+			w.write_str("\\")?;
+			last = i + 1;
+		}
+		i += 1;
+	}
+	w.write_str(&s[last ..])
+}
 
+#[derive(Clone)]
 enum Node<'a> {
-	Str(&'a str),
-	Escape(&'a str),
+	Str(Cow<'a, str>),
+	Escape(Cow<'a, str>),
 	Tag(Tag<'a>),
 }
 
-impl<'a> ToString for Node<'a> {
-	fn to_string(&self) -> String {
+impl<'a> Node<'a> {
+	fn render<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
 		match *self {
-			Node::Str(s) => String::from(s),
-			Node::Escape(s) => html::escape_html(s),
-			Node::Tag(ref tag) => tag.to_string(),
+			Node::Str(ref s) => w.write_str(s),
+			Node::Escape(ref s) => html::write_escape_html(w, s),
+			Node::Tag(ref tag) => tag.render(w),
+		}
+	}
+}
+
+// Adapts an io::Write to fmt::Write, keeping the first io error.
+struct IoWriter<'a, W: io::Write + ?Sized + 'a> {
+	inner: &'a mut W,
+	error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write + ?Sized> fmt::Write for IoWriter<'a, W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		match self.inner.write_all(s.as_bytes()) {
+			Ok(()) => Ok(()),
+			Err(e) => {
+				self.error = Some(e);
+				Err(fmt::Error)
+			},
 		}
 	}
 }
 
+fn render_io<W: io::Write + ?Sized, F>(w: &mut W, render: F) -> io::Result<()>
+	where F: FnOnce(&mut IoWriter<W>) -> fmt::Result {
+	let mut writer = IoWriter { inner: w, error: None };
+	match render(&mut writer) {
+		Ok(()) => Ok(()),
+		Err(_) => Err(writer.error.unwrap_or_else(|| io::Error::other("formatter error"))),
+	}
+}
+
+#[derive(Clone)]
 pub struct Tag<'a> {
-	name: &'a str,
-	attr: Vec<(&'a str, &'a str)>,
+	name: Cow<'a, str>,
+	attr: Vec<(Cow<'a, str>, Option<Cow<'a, str>>)>,
 	child: Vec<Node<'a>>,
 }
 
 impl<'a> Tag<'a> {
-	pub fn new(name: &'a str) -> Tag<'a> {
+	// The name is only checked in debug builds, so use `try_new` for names
+	// that are not known in advance.
+	pub fn new<S: Into<Cow<'a, str>>>(name: S) -> Tag<'a> {
+		let name = name.into();
+		debug_assert!(is_valid_tag_name(&name), "invalid tag name: {:?}", name);
 		Tag {
 			name: name,
 			attr: Vec::new(),
 			child: Vec::new(),
 		}
 	}
-	pub fn push_attr(&mut self, name: &'a str, value: &'a str) {
-		self.attr.push((name, value));
+	pub fn try_new<S: Into<Cow<'a, str>>>(name: S) -> Option<Tag<'a>> {
+		let name = name.into();
+		if ! is_valid_tag_name(&name) {
+			return None;
+		}
+		Some(Tag {
+			name: name,
+			attr: Vec::new(),
+			child: Vec::new(),
+		})
 	}
-	pub fn push_str(&mut self, s: &'a str) {
-		self.child.push(Node::Str(s));
+	// An invalid name is a bug in debug builds, and leaves the attribute
+	// out otherwise.
+	pub fn push_attr<N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(&mut self, name: N, value: V) {
+		let pushed = self.try_push_attr(name, value);
+		debug_assert!(pushed, "invalid attribute name");
 	}
-	pub fn push_escape(&mut self, s: &'a str) {
-		self.child.push(Node::Escape(s));
+	pub fn try_push_attr<N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(&mut self, name: N, value: V) -> bool {
+		let name = name.into();
+		if ! is_valid_attr_name(&name) {
+			return false;
+		}
+		self.attr.push((name, Some(value.into())));
+		true
 	}
+	// Pushes a boolean attribute such as `disabled` or `checked`.
+	pub fn push_bool_attr<N: Into<Cow<'a, str>>>(&mut self, name: N) {
+		let name = name.into();
+		let valid = is_valid_attr_name(&name);
+		debug_assert!(valid, "invalid attribute name: {:?}", name);
+		if valid {
+			self.attr.push((name, None));
+		}
+	}
+	pub fn push_html(&mut self, html: SafeHtml<'a>) {
+		self.child.push(Node::Str(html.0));
+	}
+	pub fn push_escape<S: Into<Cow<'a, str>>>(&mut self, s: S) {
+		self.child.push(Node::Escape(s.into()));
+	}
+	// Raw text elements such as `script` can't hold tags, so pushing one
+	// into them is a bug in debug builds and ignored otherwise.
 	pub fn push_tag(&mut self, tag: Tag<'a>) {
+		let pushed = self.try_push_tag(tag);
+		debug_assert!(pushed, "tag pushed into a raw text element");
+	}
+	pub fn try_push_tag(&mut self, tag: Tag<'a>) -> bool {
+		if is_raw_text_element(&self.name) {
+			return false;
+		}
 		self.child.push(Node::Tag(tag));
+		true
 	}
-}
-
-impl<'a> ToString for Tag<'a> {
-	fn to_string(&self) -> String {
-		let mut html = String::new();
-		html.push_str("<");
-		html.push_str(self.name);
-		if ! self.attr.is_empty() {
-			html.push_str(" ");
-			for &a in &self.attr {
-				let (name, value) = a;
-				html.push_str(html::escape_html(name).as_str());
-				html.push_str("=\"");
-				html.push_str(html::escape_html(value).as_str());
-				html.push_str("\"");
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+	pub fn get_attr(&self, name: &str) -> Option<&str> {
+		self.attr.iter()
+			.find(|a| a.0.eq_ignore_ascii_case(name))
+			.and_then(|a| a.1.as_ref().map(|v| v.as_ref()))
+	}
+	// Replaces the first child tag matching `pred`, or pushes `tag` if none does.
+	fn replace_tag<F: Fn(&Tag) -> bool>(&mut self, tag: Tag<'a>, pred: F) {
+		for node in self.child.iter_mut() {
+			if let Node::Tag(ref mut t) = *node {
+				if pred(t) {
+					*t = tag;
+					return;
+				}
 			}
 		}
-		if self.child.is_empty() {
-			html.push_str(" />");
+		self.push_tag(tag);
+	}
+	fn has_tag<F: Fn(&Tag) -> bool>(&self, pred: F) -> bool {
+		self.child.iter().any(|node| match *node {
+			Node::Tag(ref t) => pred(t),
+			_ => false,
+		})
+	}
+	pub fn attr<N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(mut self, name: N, value: V) -> Tag<'a> {
+		self.push_attr(name, value);
+		self
+	}
+	pub fn bool_attr<N: Into<Cow<'a, str>>>(mut self, name: N) -> Tag<'a> {
+		self.push_bool_attr(name);
+		self
+	}
+	pub fn class<V: Into<Cow<'a, str>>>(self, value: V) -> Tag<'a> {
+		self.attr("class", value)
+	}
+	pub fn id<V: Into<Cow<'a, str>>>(self, value: V) -> Tag<'a> {
+		self.attr("id", value)
+	}
+	pub fn text<S: Into<Cow<'a, str>>>(mut self, s: S) -> Tag<'a> {
+		self.push_escape(s);
+		self
+	}
+	pub fn html(mut self, html: SafeHtml<'a>) -> Tag<'a> {
+		self.push_html(html);
+		self
+	}
+	pub fn child(mut self, tag: Tag<'a>) -> Tag<'a> {
+		self.push_tag(tag);
+		self
+	}
+	pub fn children<I: IntoIterator<Item = Tag<'a>>>(mut self, tags: I) -> Tag<'a> {
+		for tag in tags {
+			self.push_tag(tag);
+		}
+		self
+	}
+	pub fn render<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		w.write_str("<")?;
+		w.write_str(&self.name)?;
+		for (name, value) in &self.attr {
+			w.write_str(" ")?;
+			w.write_str(name)?;
+			if let Some(ref value) = *value {
+				w.write_str("=\"")?;
+				if html::is_url_attr(name) {
+					html::write_escape_url_attr(w, value)?;
+				} else {
+					html::write_escape_attr(w, value)?;
+				}
+				w.write_str("\"")?;
+			}
+		}
+		w.write_str(">")?;
+		// Void elements never have content or a closing tag.
+		if is_void_element(&self.name) {
+			return Ok(());
+		}
+		self.render_children(w)?;
+		w.write_str("</")?;
+		w.write_str(&self.name)?;
+		w.write_str(">")
+	}
+	// Renders the content without the element's own start and end tags.
+	pub fn render_children<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		if is_raw_text_element(&self.name) {
+			// `try_push_tag` keeps tags out of these.
+			for node in &self.child {
+				if let Node::Str(ref s) | Node::Escape(ref s) = *node {
+					write_raw_text(w, &self.name, s)?;
+				}
+			}
 		} else {
-			html.push_str(">");
 			for node in &self.child {
-				html.push_str(node.to_string().as_str());
+				node.render(w)?;
 			}
-			html.push_str("</");
-			html.push_str(self.name);
-			html.push_str(">");
 		}
-		html
+		Ok(())
+	}
+	pub fn render_io<W: io::Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+		render_io(w, |w| self.render(w))
 	}
 }
 
+impl<'a> fmt::Display for Tag<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.render(f)
+	}
+}
+
+pub fn div<'a>() -> Tag<'a> {
+	Tag::new("div")
+}
+
+pub fn span<'a>() -> Tag<'a> {
+	Tag::new("span")
+}
+
+pub fn p<'a>() -> Tag<'a> {
+	Tag::new("p")
+}
+
+pub fn h1<'a>() -> Tag<'a> {
+	Tag::new("h1")
+}
+
+pub fn h2<'a>() -> Tag<'a> {
+	Tag::new("h2")
+}
+
+pub fn br<'a>() -> Tag<'a> {
+	Tag::new("br")
+}
+
+pub fn a<'a, H: Into<Cow<'a, str>>>(href: H) -> Tag<'a> {
+	Tag::new("a").attr("href", href)
+}
+
+pub fn img<'a, S: Into<Cow<'a, str>>, A: Into<Cow<'a, str>>>(src: S, alt: A) -> Tag<'a> {
+	Tag::new("img").attr("src", src).attr("alt", alt)
+}
+
+pub fn form<'a, M: Into<Cow<'a, str>>>(method: M) -> Tag<'a> {
+	Tag::new("form").attr("method", method)
+}
+
+pub fn label<'a, F: Into<Cow<'a, str>>>(for_id: F) -> Tag<'a> {
+	Tag::new("label").attr("for", for_id)
+}
+
+pub fn input<'a, T, N, V>(input_type: T, name: N, value: V) -> Tag<'a>
+	where T: Into<Cow<'a, str>>, N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>> {
+	Tag::new("input").attr("type", input_type).attr("name", name).attr("value", value)
+}
+
+pub fn textarea<'a, N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(name: N, value: V) -> Tag<'a> {
+	Tag::new("textarea").attr("name", name).text(value)
+}
+
+pub fn button<'a, T: Into<Cow<'a, str>>>(button_type: T) -> Tag<'a> {
+	Tag::new("button").attr("type", button_type)
+}
+
+pub fn li<'a>() -> Tag<'a> {
+	Tag::new("li")
+}
+
+// Builds a list with one escaped `li` per item.
+pub fn ul<'a, I, S>(items: I) -> Tag<'a>
+	where I: IntoIterator<Item = S>, S: Into<Cow<'a, str>> {
+	Tag::new("ul").children(items.into_iter().map(|item| li().text(item)))
+}
+
+#[derive(Clone)]
 pub struct HTML<'a> {
-	lang: &'a str,
+	lang: Cow<'a, str>,
 	pub head: Tag<'a>,
 	pub body: Tag<'a>,
 }
 
 impl<'a> HTML<'a> {
-	pub fn new(title: &'a str, lang: &'a str) -> HTML<'a> {
+	pub fn new<T: Into<Cow<'a, str>>, L: Into<Cow<'a, str>>>(title: T, lang: L) -> HTML<'a> {
+		let mut head = Tag::new("head");
+		let mut t = Tag::new("title");
+		t.push_escape(title);
+		head.push_tag(t);
 		HTML {
-			lang: lang,
-			head: Tag {
-				name: "head",
-				attr: Vec::new(),
-				child: vec![
-					Node::Tag(Tag {
-						name: "title",
-						attr: Vec::new(),
-						child: vec![
-							Node::Escape(title),
-						],
-					}),
-				],
-			},
+			lang: lang.into(),
+			head: head,
 			body: Tag::new("body"),
 		}
 	}
+	// Starts a page from a shared layout, keeping its head and body.
+	pub fn from_layout<T: Into<Cow<'a, str>>>(layout: &HTML<'a>, title: T) -> HTML<'a> {
+		let mut html = layout.clone();
+		html.set_title(title);
+		html
+	}
+	pub fn set_title<T: Into<Cow<'a, str>>>(&mut self, title: T) {
+		self.head.replace_tag(Tag::new("title").text(title), |t| t.name() == "title");
+	}
+	pub fn set_lang<L: Into<Cow<'a, str>>>(&mut self, lang: L) {
+		self.lang = lang.into();
+	}
+	// Sets the language the client prefers by `Accept-Language` out of
+	// those available, keeping the current one if it accepts none of them.
+	pub fn negotiate_lang(&mut self, req: &Request, langs: &[&'a str]) {
+		if let Some(lang) = req.negotiate_language(langs) {
+			self.lang = Cow::Borrowed(lang);
+		}
+	}
+	pub fn set_charset<C: Into<Cow<'a, str>>>(&mut self, charset: C) {
+		let tag = Tag::new("meta").attr("charset", charset);
+		let is_charset = |t: &Tag| t.name() == "meta" && t.get_attr("charset").is_some();
+		if self.head.has_tag(is_charset) {
+			self.head.replace_tag(tag, is_charset);
+		} else {
+			self.head.child.insert(0, Node::Tag(tag));
+		}
+	}
+	pub fn set_meta<N: Into<Cow<'a, str>>, C: Into<Cow<'a, str>>>(&mut self, name: N, content: C) {
+		let name = name.into();
+		let tag = Tag::new("meta").attr("name", name.clone()).attr("content", content);
+		self.head.replace_tag(tag, |t| t.name() == "meta" && t.get_attr("name") == Some(&name));
+	}
+	pub fn set_viewport<C: Into<Cow<'a, str>>>(&mut self, content: C) {
+		self.set_meta("viewport", content);
+	}
+	pub fn set_description<C: Into<Cow<'a, str>>>(&mut self, content: C) {
+		self.set_meta("description", content);
+	}
+	// Sets an Open Graph property such as `og:title` or `og:image`.
+	pub fn set_property<P: Into<Cow<'a, str>>, C: Into<Cow<'a, str>>>(&mut self, property: P, content: C) {
+		let property = property.into();
+		let tag = Tag::new("meta").attr("property", property.clone()).attr("content", content);
+		self.head.replace_tag(tag, |t| t.name() == "meta" && t.get_attr("property") == Some(&property));
+	}
+	pub fn set_favicon<H: Into<Cow<'a, str>>, T: Into<Cow<'a, str>>>(&mut self, href: H, icon_type: T) {
+		let tag = Tag::new("link").attr("rel", "icon").attr("href", href).attr("type", icon_type);
+		self.head.replace_tag(tag, |t| t.name() == "link" && t.get_attr("rel") == Some("icon"));
+	}
+	pub fn push_stylesheet<H: Into<Cow<'a, str>>>(&mut self, href: H) {
+		let href = href.into();
+		if ! self.head.has_tag(|t| t.name() == "link" && t.get_attr("rel") == Some("stylesheet") && t.get_attr("href") == Some(&href)) {
+			self.head.push_tag(Tag::new("link").attr("rel", "stylesheet").attr("href", href));
+		}
+	}
+	pub fn push_script<S: Into<Cow<'a, str>>>(&mut self, src: S) {
+		let src = src.into();
+		if ! self.head.has_tag(|t| t.name() == "script" && t.get_attr("src") == Some(&src)) {
+			self.head.push_tag(Tag::new("script").attr("src", src));
+		}
+	}
+	pub fn push_style<S: Into<Cow<'a, str>>>(&mut self, css: S) {
+		let css = css.into();
+		let exists = self.head.has_tag(|t| t.name() == "style" && match t.child.first() {
+			Some(Node::Escape(s)) => *s == css,
+			_ => false,
+		});
+		if ! exists {
+			self.head.push_tag(Tag::new("style").text(css));
+		}
+	}
+	pub fn render<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		w.write_str("<!DOCTYPE html>\n")?;
+		w.write_str("<html lang=\"")?;
+		html::write_escape_attr(w, &self.lang)?;
+		w.write_str("\">\n")?;
+		self.head.render(w)?;
+		self.body.render(w)?;
+		w.write_str("</html>\n")
+	}
+	pub fn render_io<W: io::Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+		render_io(w, |w| self.render(w))
+	}
 }
 
-impl<'a> ToString for HTML<'a> {
-	fn to_string(&self) -> String {
-		let mut html = String::new();
-		html.push_str("<!DOCTYPE html>\n");
-		html.push_str("<html lang=\"");
-		html.push_str(self.lang);
-		html.push_str("\">\n");
-		html.push_str(self.head.to_string().as_str());
-		html.push_str(self.body.to_string().as_str());
-		html.push_str("</html>\n");
-		html
+impl<'a> fmt::Display for HTML<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.render(f)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Tag;
+	use super::HTML;
+	use super::div;
+	use super::form;
+	use super::input;
+	use super::button;
+	use super::ul;
+	use super::a;
+	use html::SafeHtml;
+	use http::with_request;
+	#[test]
+	fn test_negotiate_lang() {
+		let mut html = HTML::new("title", "en");
+		with_request(&["GET / HTTP/1.1", "Accept-Language: fr, ja;q=0.8"], None, |req| html.negotiate_lang(req, &["en", "ja"]));
+		assert!(html.to_string().contains("<html lang=\"ja\">"));
+		with_request(&["GET / HTTP/1.1", "Accept-Language: de"], None, |req| html.negotiate_lang(req, &["en", "fr"]));
+		assert!(html.to_string().contains("<html lang=\"ja\">"));
+	}
+	#[test]
+	fn test_void_and_raw_text() {
+		let mut div = Tag::new("div");
+		div.push_attr("a", "1");
+		div.push_attr("b", "2");
+		assert_eq!("<div a=\"1\" b=\"2\"></div>", div.to_string());
+		let mut input = Tag::new("input");
+		input.push_attr("type", "checkbox");
+		input.push_bool_attr("checked");
+		assert_eq!("<input type=\"checkbox\" checked>", input.to_string());
+		let mut script = Tag::new("script");
+		script.push_escape("if (a < b) { s = \"</SCRIPT>\"; }");
+		assert_eq!("<script>if (a < b) { s = \"<\\/SCRIPT>\"; }</script>", script.to_string());
+		assert!(Tag::try_new("my-element").is_some());
+		assert!(Tag::try_new("1a").is_none());
+		assert!(Tag::try_new("a b").is_none());
+		assert!(! Tag::new("a").try_push_attr("on\"x", ""));
+		assert!(! Tag::new("script").try_push_tag(Tag::new("b")));
+		assert!(Tag::new("div").try_push_tag(Tag::new("b")));
+		let mut a = Tag::new("a");
+		a.push_attr("href", "javascript:alert('x')");
+		a.push_attr("title", "'x'");
+		a.push_html(SafeHtml::escape("<x>"));
+		assert_eq!("<a href=\"#\" title=\"&#39;x&#39;\">&lt;x&gt;</a>", a.to_string());
+	}
+	#[test]
+	fn test_constructors() {
+		let f = form("POST")
+			.id("f")
+			.child(input("text", "q", String::from("a\"b")).class("wide"))
+			.child(button("submit").text("SUBMIT"));
+		assert_eq!("<form method=\"POST\" id=\"f\"><input type=\"text\" name=\"q\" value=\"a&quot;b\" class=\"wide\"><button type=\"submit\">SUBMIT</button></form>", f.to_string());
+		let d = div().class("nav").child(a("/").text("top")).child(ul(vec!["<1>", "2"]));
+		assert_eq!("<div class=\"nav\"><a href=\"/\">top</a><ul><li>&lt;1&gt;</li><li>2</li></ul></div>", d.to_string());
+	}
+	#[test]
+	fn test_head() {
+		let mut layout = HTML::new("layout", "en");
+		layout.push_stylesheet("/site.css");
+		layout.set_viewport("width=device-width");
+		layout.set_charset("utf-8");
+		layout.body.push_tag(div().id("nav"));
+		let mut html = HTML::from_layout(&layout, "page");
+		html.push_stylesheet("/site.css");
+		html.push_script("/app.js");
+		html.push_script("/app.js");
+		html.set_description("old");
+		html.set_description("new");
+		html.set_property("og:title", "page");
+		html.set_favicon("/favicon.ico", "image/x-icon");
+		html.push_style("p { color: red }");
+		html.push_style("p { color: red }");
+		html.set_charset("UTF-8");
+		let expected = concat!(
+			"<head><meta charset=\"UTF-8\"><title>page</title>",
+			"<link rel=\"stylesheet\" href=\"/site.css\">",
+			"<meta name=\"viewport\" content=\"width=device-width\">",
+			"<script src=\"/app.js\"></script>",
+			"<meta name=\"description\" content=\"new\">",
+			"<meta property=\"og:title\" content=\"page\">",
+			"<link rel=\"icon\" href=\"/favicon.ico\" type=\"image/x-icon\">",
+			"<style>p { color: red }</style></head>");
+		assert_eq!(expected, html.head.to_string());
+		assert_eq!("<body><div id=\"nav\"></div></body>", html.body.to_string());
+		assert!(layout.head.to_string().contains("<title>layout</title>"));
+	}
+	#[test]
+	fn test_render() {
+		let name = String::from("a&b");
+		let mut html = HTML::new("title", "ja");
+		let mut p = Tag::new("p");
+		p.push_attr("class", format!("x{}", 1));
+		p.push_escape(name);
+		p.push_html(SafeHtml::trusted("<br />"));
+		html.body.push_tag(p);
+		let expected = "<!DOCTYPE html>\n<html lang=\"ja\">\n<head><title>title</title></head><body><p class=\"x1\">a&amp;b<br /></p></body></html>\n";
+		assert_eq!(expected, html.to_string());
+		let mut buf: Vec<u8> = Vec::new();
+		html.render_io(&mut buf).unwrap();
+		assert_eq!(expected.as_bytes(), buf.as_slice());
 	}
 }
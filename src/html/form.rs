@@ -0,0 +1,390 @@
+use std::str::FromStr;
+
+use Params;
+use Request;
+
+use html::builder::Tag;
+use html::builder::form;
+use html::builder::input;
+use html::builder::label;
+use html::builder::textarea;
+use html::builder::button;
+use html::builder::span;
+use html::builder::div;
+
+pub enum FieldType {
+	Text,
+	Password,
+	Textarea,
+	Select(Vec<(String, String)>),
+	Checkbox,
+	Radio(Vec<(String, String)>),
+	Hidden,
+	File,
+}
+
+pub type Validator = Box<Fn(&str) -> Option<String>>;
+
+pub enum Rule {
+	Required,
+	MinLength(usize),
+	MaxLength(usize),
+	Integer,
+	Range(i64, i64),
+	Custom(Validator),
+}
+
+pub struct Field {
+	name: String,
+	label: String,
+	field_type: FieldType,
+	rules: Vec<Rule>,
+	value: Option<String>,
+	error: Option<String>,
+}
+
+fn options(choices: &[(&str, &str)]) -> Vec<(String, String)> {
+	choices.iter().map(|&(value, text)| (String::from(value), String::from(text))).collect()
+}
+
+impl Field {
+
+	pub fn new(name: &str, label: &str, field_type: FieldType) -> Field {
+		Field {
+			name: String::from(name),
+			label: String::from(label),
+			field_type: field_type,
+			rules: Vec::new(),
+			value: None,
+			error: None,
+		}
+	}
+
+	pub fn text(name: &str, label: &str) -> Field {
+		Field::new(name, label, FieldType::Text)
+	}
+	pub fn password(name: &str, label: &str) -> Field {
+		Field::new(name, label, FieldType::Password)
+	}
+	pub fn textarea(name: &str, label: &str) -> Field {
+		Field::new(name, label, FieldType::Textarea)
+	}
+	pub fn select(name: &str, label: &str, choices: &[(&str, &str)]) -> Field {
+		Field::new(name, label, FieldType::Select(options(choices)))
+	}
+	pub fn checkbox(name: &str, label: &str) -> Field {
+		Field::new(name, label, FieldType::Checkbox)
+	}
+	pub fn radio(name: &str, label: &str, choices: &[(&str, &str)]) -> Field {
+		Field::new(name, label, FieldType::Radio(options(choices)))
+	}
+	// The value is declared by the server and kept when binding, whatever
+	// the client submits.
+	pub fn hidden(name: &str, value: &str) -> Field {
+		let mut field = Field::new(name, "", FieldType::Hidden);
+		field.value = Some(String::from(value));
+		field
+	}
+	// Binding with `bind_request` only keeps the submitted file name. The
+	// contents are in the part of the same name in `Request::multipart`.
+	pub fn file(name: &str, label: &str) -> Field {
+		Field::new(name, label, FieldType::File)
+	}
+
+	pub fn rule(mut self, rule: Rule) -> Field {
+		self.rules.push(rule);
+		self
+	}
+	pub fn required(self) -> Field {
+		self.rule(Rule::Required)
+	}
+	pub fn min_length(self, n: usize) -> Field {
+		self.rule(Rule::MinLength(n))
+	}
+	pub fn max_length(self, n: usize) -> Field {
+		self.rule(Rule::MaxLength(n))
+	}
+	pub fn integer(self) -> Field {
+		self.rule(Rule::Integer)
+	}
+	pub fn range(self, min: i64, max: i64) -> Field {
+		self.rule(Rule::Range(min, max))
+	}
+	pub fn value(mut self, value: &str) -> Field {
+		self.value = Some(String::from(value));
+		self
+	}
+
+	pub fn name(&self) -> &str {
+		self.name.as_str()
+	}
+	pub fn error(&self) -> Option<&str> {
+		self.error.as_deref()
+	}
+
+	fn validate(&self) -> Option<String> {
+		let value = self.value.as_deref().unwrap_or("");
+		if value.is_empty() {
+			if self.rules.iter().any(|r| match *r { Rule::Required => true, _ => false }) {
+				return Some(format!("{} is required.", self.label));
+			}
+			return None;
+		}
+		match self.field_type {
+			FieldType::Select(ref choices) | FieldType::Radio(ref choices) if ! choices.iter().any(|c| c.0 == value) => {
+				return Some(format!("{} is not a valid choice.", self.label));
+			},
+			_ => {},
+		}
+		for rule in &self.rules {
+			let length = value.chars().count();
+			match *rule {
+				Rule::Required => {},
+				Rule::MinLength(n) if length < n => {
+					return Some(format!("{} must be at least {} characters.", self.label, n));
+				},
+				Rule::MaxLength(n) if length > n => {
+					return Some(format!("{} must be at most {} characters.", self.label, n));
+				},
+				Rule::Integer if value.parse::<i64>().is_err() => {
+					return Some(format!("{} must be an integer.", self.label));
+				},
+				Rule::Range(min, max) => {
+					match value.parse::<i64>() {
+						Ok(n) if min <= n && n <= max => {},
+						_ => return Some(format!("{} must be between {} and {}.", self.label, min, max)),
+					}
+				},
+				Rule::Custom(ref f) => {
+					if let Some(error) = f(value) {
+						return Some(error);
+					}
+				},
+				_ => {},
+			}
+		}
+		None
+	}
+
+	fn control(&self) -> Tag {
+		let name = self.name.as_str();
+		let value = self.value.as_deref().unwrap_or("");
+		match self.field_type {
+			FieldType::Text => input("text", name, value).id(name),
+			FieldType::Password => input("password", name, "").id(name),
+			FieldType::Hidden => input("hidden", name, value),
+			FieldType::File => Tag::new("input").attr("type", "file").attr("name", name).id(name),
+			FieldType::Textarea => textarea(name, value).id(name),
+			FieldType::Checkbox => {
+				let tag = input("checkbox", name, "on").id(name);
+				if value.is_empty() { tag } else { tag.bool_attr("checked") }
+			},
+			FieldType::Select(ref choices) => {
+				Tag::new("select").attr("name", name).id(name).children(choices.iter().map(|c| {
+					let option = Tag::new("option").attr("value", c.0.as_str()).text(c.1.as_str());
+					if c.0 == value { option.bool_attr("selected") } else { option }
+				}))
+			},
+			// A group has no single control for a label, so its label is the
+			// legend of a fieldset instead.
+			FieldType::Radio(ref choices) => {
+				Tag::new("fieldset").child(Tag::new("legend").text(self.label.as_str())).children(choices.iter().map(|c| {
+					let radio = input("radio", name, c.0.as_str());
+					let radio = if c.0 == value { radio.bool_attr("checked") } else { radio };
+					Tag::new("label").child(radio).text(c.1.as_str())
+				}))
+			},
+		}
+	}
+
+	pub fn to_tag(&self) -> Tag {
+		if let FieldType::Hidden = self.field_type {
+			return self.control();
+		}
+		let mut tag = div().class(if self.error.is_some() { "field error" } else { "field" });
+		if let FieldType::Checkbox = self.field_type {
+			tag.push_tag(self.control());
+			tag.push_tag(label(self.name.as_str()).text(self.label.as_str()));
+		} else if let FieldType::Radio(_) = self.field_type {
+			tag.push_tag(self.control());
+		} else {
+			tag.push_tag(label(self.name.as_str()).text(self.label.as_str()));
+			tag.push_tag(self.control());
+		}
+		if let Some(ref error) = self.error {
+			tag.push_tag(span().class("error").text(error.as_str()));
+		}
+		tag
+	}
+}
+
+pub struct Values {
+	values: Vec<(String, String)>,
+}
+
+impl Values {
+	pub fn get_str(&self, name: &str) -> Option<&str> {
+		self.values.iter().find(|v| v.0 == name).map(|v| v.1.as_str()).filter(|v| ! v.is_empty())
+	}
+	pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+		self.get_str(name).and_then(|v| v.parse().ok())
+	}
+	pub fn is_checked(&self, name: &str) -> bool {
+		self.get_str(name).is_some()
+	}
+}
+
+pub struct Form {
+	method: String,
+	action: Option<String>,
+	submit: String,
+	fields: Vec<Field>,
+}
+
+impl Form {
+
+	pub fn new(method: &str) -> Form {
+		Form {
+			method: String::from(method),
+			action: None,
+			submit: String::from("Submit"),
+			fields: Vec::new(),
+		}
+	}
+
+	pub fn set_action(&mut self, action: &str) {
+		self.action = Some(String::from(action));
+	}
+	pub fn set_submit(&mut self, submit: &str) {
+		self.submit = String::from(submit);
+	}
+	pub fn push_field(&mut self, field: Field) {
+		self.fields.push(field);
+	}
+	pub fn field(&self, name: &str) -> Option<&Field> {
+		self.fields.iter().find(|f| f.name == name)
+	}
+
+	// Binds the submitted values and validates them, keeping the values and
+	// any errors for re-rendering. Returns the values if all fields are valid.
+	pub fn bind(&mut self, params: Params) -> Option<Values> {
+		self.bind_values(params.map(|p| (p.name(), p.value())).collect())
+	}
+	// Binds the body of a POST request, which must be multipart if the form
+	// has a file field.
+	pub fn bind_request(&mut self, req: &Request) -> Option<Values> {
+		match req.multipart() {
+			Some(parts) => self.bind_values(parts.iter().map(|p| {
+				let value = match p.filename {
+					Some(ref filename) => filename.clone(),
+					None => String::from_utf8_lossy(p.data).into_owned(),
+				};
+				(p.name.clone(), value)
+			}).collect()),
+			None => self.bind(req.post_params()),
+		}
+	}
+
+	fn bind_values(&mut self, params: Vec<(String, String)>) -> Option<Values> {
+		let mut valid = true;
+		for field in self.fields.iter_mut() {
+			if let FieldType::Hidden = field.field_type {
+				continue;
+			}
+			field.value = params.iter().find(|p| p.0 == field.name).map(|p| p.1.clone());
+			field.error = field.validate();
+			valid = valid && field.error.is_none();
+		}
+		if ! valid {
+			return None;
+		}
+		Some(Values {
+			values: self.fields.iter().map(|f| (f.name.clone(), f.value.clone().unwrap_or_default())).collect(),
+		})
+	}
+
+	pub fn to_tag(&self) -> Tag {
+		let mut tag = form(self.method.as_str());
+		if let Some(ref action) = self.action {
+			tag.push_attr("action", action.as_str());
+		}
+		if self.fields.iter().any(|f| match f.field_type { FieldType::File => true, _ => false }) {
+			tag.push_attr("enctype", "multipart/form-data");
+		}
+		for field in &self.fields {
+			tag.push_tag(field.to_tag());
+		}
+		tag.push_tag(button("submit").text(self.submit.as_str()));
+		tag
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Form;
+	use super::Field;
+	use Params;
+	use http::with_request;
+	fn form() -> Form {
+		let mut form = Form::new("POST");
+		form.push_field(Field::text("name", "Name").required().max_length(5));
+		form.push_field(Field::text("age", "Age").range(0, 150));
+		form.push_field(Field::select("color", "Color", &[("r", "Red"), ("g", "Green")]));
+		form.push_field(Field::checkbox("agree", "Agree").required());
+		form.push_field(Field::radio("size", "Size", &[("s", "Small"), ("l", "Large")]));
+		form.push_field(Field::hidden("step", "2"));
+		form
+	}
+	#[test]
+	fn test_bind_valid() {
+		let mut form = form();
+		let values = form.bind(Params { query: Some(b"name=abc&age=42&color=g&agree=on&size=l&step=9") }).unwrap();
+		assert_eq!(Some("abc"), values.get_str("name"));
+		assert_eq!(Some(42), values.get::<i64>("age"));
+		assert_eq!(Some("g"), values.get_str("color"));
+		assert!(values.is_checked("agree"));
+		assert_eq!(Some("2"), values.get_str("step"));
+		let html = form.to_tag().to_string();
+		assert!(html.contains("<option value=\"g\" selected>Green</option>"));
+		assert!(html.contains("<fieldset><legend>Size</legend><label><input type=\"radio\" name=\"size\" value=\"s\">Small</label><label><input type=\"radio\" name=\"size\" value=\"l\" checked>Large</label></fieldset>"));
+		assert!(! html.contains("for=\"size\""));
+		assert!(html.contains("<input type=\"hidden\" name=\"step\" value=\"2\">"));
+	}
+	#[test]
+	fn test_bind_invalid() {
+		let mut form = form();
+		assert!(form.bind(Params { query: Some(b"name=%3Cabcdef%3E&age=x&color=b") }).is_none());
+		assert_eq!(Some("Name must be at most 5 characters."), form.field("name").unwrap().error());
+		assert_eq!(Some("Age must be between 0 and 150."), form.field("age").unwrap().error());
+		assert_eq!(Some("Color is not a valid choice."), form.field("color").unwrap().error());
+		assert_eq!(Some("Agree is required."), form.field("agree").unwrap().error());
+		let html = form.to_tag().to_string();
+		assert!(html.contains("<input type=\"text\" name=\"name\" value=\"&lt;abcdef&gt;\" id=\"name\"><span class=\"error\">"));
+		assert!(html.contains("<input type=\"checkbox\" name=\"agree\" value=\"on\" id=\"agree\"><label for=\"agree\">Agree</label>"));
+	}
+	#[test]
+	fn test_file() {
+		let mut form = Form::new("POST");
+		form.push_field(Field::text("title", "Title").required());
+		form.push_field(Field::file("photo", "Photo").required());
+		let html = form.to_tag().to_string();
+		assert!(html.starts_with("<form method=\"POST\" enctype=\"multipart/form-data\">"));
+		assert!(html.contains("<label for=\"photo\">Photo</label><input type=\"file\" name=\"photo\" id=\"photo\">"));
+		// The body a browser sends for the form above.
+		let content_type = "Content-Type: multipart/form-data; boundary=----x";
+		let body = b"------x\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nMy cat\r\n------x\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"cat.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n\xff\xd8\r\n------x--\r\n";
+		let values = with_request(&["POST / HTTP/1.1", content_type], Some(body), |req| {
+			assert_eq!(Some(&b"\xff\xd8"[..]), req.multipart().unwrap().iter().find(|p| p.name == "photo").map(|p| p.data));
+			form.bind_request(req)
+		}).unwrap();
+		assert_eq!(Some("My cat"), values.get_str("title"));
+		assert_eq!(Some("cat.jpg"), values.get_str("photo"));
+		let body = b"------x\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nMy cat\r\n------x\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"\"\r\nContent-Type: application/octet-stream\r\n\r\n\r\n------x--\r\n";
+		assert!(with_request(&["POST / HTTP/1.1", content_type], Some(body), |req| form.bind_request(req)).is_none());
+		assert_eq!(None, form.field("title").unwrap().error());
+		assert_eq!(Some("Photo is required."), form.field("photo").unwrap().error());
+		// Forms without files are still bound from urlencoded bodies.
+		let values = with_request(&["POST / HTTP/1.1"], Some(b"title=x&photo=y"), |req| form.bind_request(req)).unwrap();
+		assert_eq!(Some("x"), values.get_str("title"));
+	}
+}
@@ -1,24 +1,261 @@
+use std::borrow::Cow;
+use std::fmt;
+
 pub mod builder;
+pub mod form;
+pub mod parser;
+pub mod sanitizer;
+pub mod template;
+
+fn write_escape<W, F>(w: &mut W, s: &str, escape: F) -> fmt::Result
+	where W: fmt::Write + ?Sized, F: Fn(char) -> Option<&'static str> {
+	let mut last = 0;
+	for (i, c) in s.char_indices() {
+		if let Some(e) = escape(c) {
+			w.write_str(&s[last .. i])?;
+			w.write_str(e)?;
+			last = i + c.len_utf8();
+		}
+	}
+	w.write_str(&s[last ..])
+}
+
+pub fn write_escape_html<W: fmt::Write + ?Sized>(w: &mut W, s: &str) -> fmt::Result {
+	write_escape(w, s, |c| match c {
+		'<' => Some("&lt;"),
+		'>' => Some("&gt;"),
+		'"' => Some("&quot;"),
+		'&' => Some("&amp;"),
+		_ => None,
+	})
+}
 
 pub fn escape_html(s: &str) -> String {
-	let mut result = String::new();
+	let mut result = String::with_capacity(s.len());
+	let _ = write_escape_html(&mut result, s);
+	result
+}
+
+// A common subset of the HTML5 named character references.
+const ENTITIES: [(&str, char); 52] = [
+	("amp", '&'), ("lt", '<'), ("gt", '>'), ("quot", '"'), ("apos", '\''),
+	("nbsp", '\u{A0}'), ("iexcl", '¡'), ("cent", '¢'), ("pound", '£'), ("curren", '¤'),
+	("yen", '¥'), ("brvbar", '¦'), ("sect", '§'), ("uml", '¨'), ("copy", '©'),
+	("ordf", 'ª'), ("laquo", '«'), ("not", '¬'), ("shy", '\u{AD}'), ("reg", '®'),
+	("macr", '¯'), ("deg", '°'), ("plusmn", '±'), ("sup2", '²'), ("sup3", '³'),
+	("acute", '´'), ("micro", 'µ'), ("para", '¶'), ("middot", '·'), ("cedil", '¸'),
+	("sup1", '¹'), ("ordm", 'º'), ("raquo", '»'), ("frac14", '¼'), ("frac12", '½'),
+	("frac34", '¾'), ("iquest", '¿'), ("times", '×'), ("divide", '÷'), ("ndash", '–'),
+	("mdash", '—'), ("lsquo", '‘'), ("rsquo", '’'), ("ldquo", '“'), ("rdquo", '”'),
+	("bull", '•'), ("hellip", '…'), ("prime", '′'), ("euro", '€'), ("trade", '™'),
+	("larr", '←'), ("rarr", '→'),
+];
+
+// Decodes one character reference at the start of `s` (just after the
+// `&`), returning the character and the number of bytes consumed.
+fn unescape_reference(s: &str) -> Option<(char, usize)> {
+	if let Some(rest) = s.strip_prefix('#') {
+		let (digits, radix, offset) = match rest.strip_prefix('x').or_else(|| rest.strip_prefix('X')) {
+			Some(hex) => (hex, 16, 2),
+			None => (rest, 10, 1),
+		};
+		let end = digits.find(|c: char| ! c.is_digit(radix)).unwrap_or(digits.len());
+		if end == 0 {
+			return None;
+		}
+		let code = if end > 8 { 0x110000 } else { u32::from_str_radix(&digits[.. end], radix).unwrap_or(0x110000) };
+		let c = match code {
+			0 => '\u{FFFD}',
+			_ => ::std::char::from_u32(code).unwrap_or('\u{FFFD}'),
+		};
+		let semicolon = if digits[end ..].starts_with(';') { 1 } else { 0 };
+		return Some((c, offset + end + semicolon));
+	}
+	let end = s.find(';')?;
+	ENTITIES.iter().find(|e| e.0 == &s[.. end]).map(|e| (e.1, end + 1))
+}
+
+pub fn unescape_html(s: &str) -> String {
+	let mut result = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(pos) = rest.find('&') {
+		result.push_str(&rest[.. pos]);
+		rest = &rest[pos + 1 ..];
+		match unescape_reference(rest) {
+			Some((c, len)) => {
+				result.push(c);
+				rest = &rest[len ..];
+			},
+			None => result.push('&'),
+		}
+	}
+	result.push_str(rest);
+	result
+}
+
+// Safe inside both double- and single-quoted attribute values.
+pub fn write_escape_attr<W: fmt::Write + ?Sized>(w: &mut W, s: &str) -> fmt::Result {
+	write_escape(w, s, |c| match c {
+		'<' => Some("&lt;"),
+		'>' => Some("&gt;"),
+		'"' => Some("&quot;"),
+		'\'' => Some("&#39;"),
+		'&' => Some("&amp;"),
+		_ => None,
+	})
+}
+
+pub fn escape_attr(s: &str) -> String {
+	let mut result = String::with_capacity(s.len());
+	let _ = write_escape_attr(&mut result, s);
+	result
+}
+
+// Escapes for the inside of a quoted JavaScript string, including one
+// embedded in a <script> element or an event handler attribute.
+pub fn write_escape_js<W: fmt::Write + ?Sized>(w: &mut W, s: &str) -> fmt::Result {
 	for c in s.chars() {
 		match c {
-			'<' => { result.push_str("&lt;"); },
-			'>' => { result.push_str("&gt;"); },
-			'"' => { result.push_str("&quot;"); },
-			'&' => { result.push_str("&amp;"); },
-			_ => { result.push(c); },
+			'\\' => w.write_str("\\\\")?,
+			'\n' => w.write_str("\\n")?,
+			'\r' => w.write_str("\\r")?,
+			'\t' => w.write_str("\\t")?,
+			'"' | '\'' | '`' | '<' | '>' | '&' | '/' | '=' | '\u{2028}' | '\u{2029}' => {
+				write!(w, "\\u{:04X}", c as u32)?
+			},
+			_ if c.is_control() => write!(w, "\\u{:04X}", c as u32)?,
+			_ => w.write_char(c)?,
+		}
+	}
+	Ok(())
+}
+
+pub fn escape_js(s: &str) -> String {
+	let mut result = String::with_capacity(s.len());
+	let _ = write_escape_js(&mut result, s);
+	result
+}
+
+// Escapes for a CSS string or identifier using hex escapes.
+pub fn write_escape_css<W: fmt::Write + ?Sized>(w: &mut W, s: &str) -> fmt::Result {
+	for c in s.chars() {
+		if c.is_ascii_alphanumeric() || (c as u32) >= 0x80 && ! c.is_control() {
+			w.write_char(c)?;
+		} else {
+			write!(w, "\\{:X} ", c as u32)?;
 		}
 	}
+	Ok(())
+}
+
+pub fn escape_css(s: &str) -> String {
+	let mut result = String::with_capacity(s.len());
+	let _ = write_escape_css(&mut result, s);
 	result
 }
 
+const UNSAFE_SCHEMES: [&str; 3] = ["javascript", "vbscript", "data"];
+
+pub fn is_url_attr(name: &str) -> bool {
+	["href", "src", "action", "formaction", "cite", "poster", "background", "longdesc", "usemap", "manifest", "srcset"]
+		.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+// Browsers ignore leading spaces and embedded tabs and newlines in a
+// scheme, so they are ignored here too.
+pub fn is_safe_url(url: &str) -> bool {
+	let url = url.trim_start_matches(|c: char| c <= ' ');
+	let mut scheme = String::new();
+	for c in url.chars() {
+		match c {
+			':' => return ! UNSAFE_SCHEMES.iter().any(|x| x.eq_ignore_ascii_case(&scheme)),
+			'/' | '?' | '#' => return true,
+			'\t' | '\n' | '\r' => {},
+			_ => scheme.push(c),
+		}
+	}
+	true
+}
+
+// Writes an escaped URL attribute value, replacing unsafe URLs with `#`.
+pub fn write_escape_url_attr<W: fmt::Write + ?Sized>(w: &mut W, url: &str) -> fmt::Result {
+	if is_safe_url(url) {
+		write_escape_attr(w, url)
+	} else {
+		w.write_str("#")
+	}
+}
+
+pub fn escape_url_attr(url: &str) -> Option<String> {
+	if is_safe_url(url) {
+		Some(escape_attr(url))
+	} else {
+		None
+	}
+}
+
+// HTML that is already escaped or otherwise trusted. It is written as is
+// wherever it is used, so it can only be built by escaping text or by an
+// explicit `SafeHtml::trusted`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct SafeHtml<'a>(Cow<'a, str>);
+
+impl<'a> SafeHtml<'a> {
+	pub fn escape(s: &str) -> SafeHtml<'static> {
+		SafeHtml(Cow::Owned(escape_html(s)))
+	}
+	pub fn trusted<S: Into<Cow<'a, str>>>(s: S) -> SafeHtml<'a> {
+		SafeHtml(s.into())
+	}
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+	pub fn into_string(self) -> String {
+		self.0.into_owned()
+	}
+}
+
+impl<'a> fmt::Display for SafeHtml<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl<'a, 'b> From<builder::Tag<'b>> for SafeHtml<'a> {
+	fn from(tag: builder::Tag<'b>) -> SafeHtml<'a> {
+		SafeHtml(Cow::Owned(tag.to_string()))
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::escape_html;
+	use super::unescape_html;
+	use super::escape_attr;
+	use super::escape_js;
+	use super::escape_css;
+	use super::escape_url_attr;
+	use super::SafeHtml;
 	#[test]
 	fn test_escape_html() {
 		assert_eq!("&lt;&gt;&quot;&amp;", escape_html("<>\"&"));
 	}
+	#[test]
+	fn test_unescape_html() {
+		assert_eq!("<>\"&", unescape_html(&escape_html("<>\"&")));
+		assert_eq!("© ©A😀 \u{FFFD}", unescape_html("&copy; &#169;&#x41&#X1F600; &#0;"));
+		assert_eq!("&foo; & &#; &amp", unescape_html("&foo; & &#; &amp"));
+	}
+	#[test]
+	fn test_escape_contexts() {
+		assert_eq!("&lt;&gt;&quot;&#39;&amp;あ", escape_attr("<>\"'&あ"));
+		assert_eq!("a\\u0027\\u0022\\n\\u003C\\u002Fscript\\u003E\\u2028", escape_js("a'\"\n</script>\u{2028}"));
+		assert_eq!("a\\3C \\2F style\\3E あ", escape_css("a</style>あ"));
+		assert_eq!(Some(String::from("/a?b=&#39;c&#39;")), escape_url_attr("/a?b='c'"));
+		assert_eq!(Some(String::from("http://example.com/")), escape_url_attr("http://example.com/"));
+		assert_eq!(None, escape_url_attr(" JavaScript:alert(1)"));
+		assert_eq!(None, escape_url_attr("java\tscript:alert(1)"));
+		assert_eq!(None, escape_url_attr("data:text/html,x"));
+		assert_eq!("&lt;b&gt;", SafeHtml::escape("<b>").as_str());
+	}
 }
@@ -0,0 +1,213 @@
+use html;
+use html::builder::is_raw_text_element;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Token {
+	StartTag(String, Vec<(String, Option<String>)>, bool),
+	EndTag(String),
+	Text(String),
+	Comment(String),
+	Doctype(String),
+}
+
+// Splits HTML into tokens. Tag and attribute names are lower-cased and
+// character references in text and attribute values are decoded. The
+// content of raw text elements such as <script> is returned as a single
+// undecoded text token.
+pub struct Tokenizer<'a> {
+	input: &'a str,
+	pos: usize,
+	raw_text: Option<String>,
+}
+
+fn is_name_char(c: char) -> bool {
+	! c.is_whitespace() && c != '/' && c != '>' && c != '='
+}
+
+impl<'a> Tokenizer<'a> {
+
+	pub fn new(input: &'a str) -> Tokenizer<'a> {
+		Tokenizer {
+			input: input,
+			pos: 0,
+			raw_text: None,
+		}
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.input[self.pos ..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		let rest = self.rest();
+		self.pos += rest.len() - rest.trim_start().len();
+	}
+
+	fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+		let rest = self.rest();
+		let end = rest.find(|c| ! f(c)).unwrap_or(rest.len());
+		self.pos += end;
+		&rest[.. end]
+	}
+
+	fn attribute_value(&mut self) -> String {
+		let rest = self.rest();
+		let value = match rest.chars().next() {
+			Some(q) if q == '"' || q == '\'' => {
+				match rest[1 ..].find(q) {
+					Some(end) => {
+						self.pos += end + 2;
+						&rest[1 .. end + 1]
+					},
+					None => {
+						self.pos += rest.len();
+						&rest[1 ..]
+					},
+				}
+			},
+			_ => self.take_while(|c| ! c.is_whitespace() && c != '>'),
+		};
+		html::unescape_html(value)
+	}
+
+	fn tag(&mut self) -> Token {
+		let end = self.rest().starts_with("</");
+		self.pos += if end { 2 } else { 1 };
+		let name = self.take_while(is_name_char).to_ascii_lowercase();
+		let mut attrs: Vec<(String, Option<String>)> = Vec::new();
+		let mut self_closing = false;
+		loop {
+			self.skip_whitespace();
+			let rest = self.rest();
+			if rest.is_empty() {
+				break;
+			} else if rest.starts_with('>') {
+				self.pos += 1;
+				break;
+			} else if rest.starts_with("/>") {
+				self.pos += 2;
+				self_closing = true;
+				break;
+			} else if rest.starts_with('/') || rest.starts_with('=') {
+				self.pos += 1;
+				continue;
+			}
+			let attr = self.take_while(is_name_char).to_ascii_lowercase();
+			self.skip_whitespace();
+			let value = if self.rest().starts_with('=') {
+				self.pos += 1;
+				self.skip_whitespace();
+				Some(self.attribute_value())
+			} else {
+				None
+			};
+			if ! attrs.iter().any(|a| a.0 == attr) {
+				attrs.push((attr, value));
+			}
+		}
+		if end {
+			Token::EndTag(name)
+		} else {
+			Token::StartTag(name, attrs, self_closing)
+		}
+	}
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+	type Item = Token;
+	fn next(&mut self) -> Option<Token> {
+		if let Some(name) = self.raw_text.take() {
+			let rest = self.rest();
+			let close = format!("</{}", name);
+			let end = rest.to_ascii_lowercase().find(close.as_str()).unwrap_or(rest.len());
+			self.pos += end;
+			if end > 0 {
+				return Some(Token::Text(String::from(&rest[.. end])));
+			}
+		}
+		let rest = self.rest();
+		if rest.is_empty() {
+			return None;
+		}
+		if let Some(comment) = rest.strip_prefix("<!--") {
+			let (comment, len) = match comment.find("-->") {
+				Some(end) => (&comment[.. end], end + 7),
+				None => (comment, rest.len()),
+			};
+			self.pos += len;
+			return Some(Token::Comment(String::from(comment)));
+		}
+		if rest.starts_with("<!") || rest.starts_with("<?") {
+			let (doctype, len) = match rest.find('>') {
+				Some(end) => (&rest[2 .. end], end + 1),
+				None => (&rest[2 ..], rest.len()),
+			};
+			self.pos += len;
+			return Some(Token::Doctype(String::from(doctype)));
+		}
+		let mut chars = rest.chars();
+		if chars.next() == Some('<') {
+			let is_tag = match chars.next() {
+				Some('/') => chars.next().is_some_and(|c| c.is_ascii_alphabetic()),
+				Some(c) => c.is_ascii_alphabetic(),
+				None => false,
+			};
+			if is_tag {
+				let token = self.tag();
+				if let Token::StartTag(ref name, _, false) = token {
+					if is_raw_text_element(name) {
+						self.raw_text = Some(name.clone());
+					}
+				}
+				return Some(token);
+			}
+		}
+		let first = rest.chars().next().map_or(1, char::len_utf8);
+		let end = rest[first ..].find('<').map_or(rest.len(), |end| end + first);
+		self.pos += end;
+		Some(Token::Text(html::unescape_html(&rest[.. end])))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Tokenizer;
+	use super::Token;
+	#[test]
+	fn test_tokenize() {
+		let tokens: Vec<Token> = Tokenizer::new("<!DOCTYPE html><P Class='a&amp;b' id=x checked>1 &lt; 2<br/></p><!-- c --><script>if (a<b) {}</SCRIPT>a < b").collect();
+		assert_eq!(vec![
+			Token::Doctype(String::from("DOCTYPE html")),
+			Token::StartTag(String::from("p"), vec![
+				(String::from("class"), Some(String::from("a&b"))),
+				(String::from("id"), Some(String::from("x"))),
+				(String::from("checked"), None),
+			], false),
+			Token::Text(String::from("1 < 2")),
+			Token::StartTag(String::from("br"), vec![], true),
+			Token::EndTag(String::from("p")),
+			Token::Comment(String::from(" c ")),
+			Token::StartTag(String::from("script"), vec![], false),
+			Token::Text(String::from("if (a<b) {}")),
+			Token::EndTag(String::from("script")),
+			Token::Text(String::from("a ")),
+			Token::Text(String::from("< b")),
+		], tokens);
+	}
+	#[test]
+	fn test_tokenize_unterminated() {
+		assert_eq!(vec![Token::StartTag(String::from("a"), vec![(String::from("href"), Some(String::from("x")))], false)], Tokenizer::new("<a href=\"x").collect::<Vec<Token>>());
+		assert_eq!(vec![Token::Comment(String::from(" x"))], Tokenizer::new("<!-- x").collect::<Vec<Token>>());
+		assert_eq!(vec![Token::Text(String::from("<"))], Tokenizer::new("<").collect::<Vec<Token>>());
+	}
+	#[test]
+	fn test_tokenize_non_ascii() {
+		assert_eq!(vec![
+			Token::StartTag(String::from("p"), vec![(String::from("title"), Some(String::from("日本")))], false),
+			Token::Text(String::from("あいう")),
+			Token::EndTag(String::from("p")),
+			Token::Text(String::from("é")),
+			Token::Text(String::from("<")),
+		], Tokenizer::new("<p title=日本>あいう</p>é<").collect::<Vec<Token>>());
+	}
+}
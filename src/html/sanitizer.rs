@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use html;
+use html::SafeHtml;
+use html::builder::Tag;
+use html::builder::is_void_element;
+use html::parser::Token;
+use html::parser::Tokenizer;
+
+// Elements whose content is dropped along with the element itself.
+const DROP_CONTENT: [&str; 7] = ["script", "style", "iframe", "object", "template", "textarea", "noscript"];
+
+pub struct Sanitizer {
+	tags: HashMap<String, Vec<String>>,
+	schemes: Vec<String>,
+}
+
+impl Default for Sanitizer {
+	fn default() -> Sanitizer {
+		Sanitizer::new()
+	}
+}
+
+impl Sanitizer {
+
+	// An empty whitelist that only keeps text.
+	pub fn new() -> Sanitizer {
+		Sanitizer {
+			tags: HashMap::new(),
+			schemes: Vec::new(),
+		}
+	}
+
+	// A whitelist suitable for user comments.
+	pub fn basic() -> Sanitizer {
+		let mut sanitizer = Sanitizer::new();
+		for tag in &["p", "br", "b", "i", "em", "strong", "u", "s", "code", "pre", "blockquote", "ul", "ol", "li"] {
+			sanitizer.allow_tag(tag, &[]);
+		}
+		sanitizer.allow_tag("a", &["href", "title"]);
+		for scheme in &["http", "https", "mailto"] {
+			sanitizer.allow_scheme(scheme);
+		}
+		sanitizer
+	}
+
+	pub fn allow_tag(&mut self, name: &str, attrs: &[&str]) {
+		let name = name.to_ascii_lowercase();
+		let list = self.tags.entry(name).or_default();
+		for attr in attrs {
+			list.push(attr.to_ascii_lowercase());
+		}
+	}
+
+	pub fn allow_scheme(&mut self, scheme: &str) {
+		self.schemes.push(scheme.to_ascii_lowercase());
+	}
+
+	// Relative URLs are always allowed; absolute ones need a whitelisted scheme.
+	fn is_allowed_url(&self, url: &str) -> bool {
+		if ! html::is_safe_url(url) {
+			return false;
+		}
+		let url = url.trim();
+		match url.find([':', '/', '?', '#']) {
+			Some(pos) if url[pos ..].starts_with(':') => {
+				self.schemes.iter().any(|s| s.eq_ignore_ascii_case(&url[.. pos]))
+			},
+			_ => true,
+		}
+	}
+
+	fn close(stack: &mut Vec<Tag<'static>>, parent: &mut Tag<'static>) {
+		if let Some(tag) = stack.pop() {
+			match stack.last_mut() {
+				Some(top) => top.push_tag(tag),
+				None => parent.push_tag(tag),
+			}
+		}
+	}
+
+	// Parses `input` and appends whitelisted elements and all text to `parent`.
+	pub fn sanitize_into(&self, input: &str, parent: &mut Tag<'static>) {
+		let mut stack: Vec<Tag<'static>> = Vec::new();
+		let mut dropping: Option<String> = None;
+		for token in Tokenizer::new(input) {
+			if let Some(ref name) = dropping {
+				if token == Token::EndTag(name.clone()) {
+					dropping = None;
+				}
+				continue;
+			}
+			match token {
+				Token::Text(text) => {
+					match stack.last_mut() {
+						Some(top) => top.push_escape(text),
+						None => parent.push_escape(text),
+					}
+				},
+				Token::StartTag(name, attrs, self_closing) => {
+					let allowed = match self.tags.get(&name) {
+						Some(allowed) => allowed,
+						None => {
+							if DROP_CONTENT.contains(&name.as_str()) && ! self_closing {
+								dropping = Some(name);
+							}
+							continue;
+						},
+					};
+					let mut tag = match Tag::try_new(name.clone()) {
+						Some(tag) => tag,
+						None => continue,
+					};
+					for (attr, value) in attrs {
+						if ! allowed.contains(&attr) {
+							continue;
+						}
+						match value {
+							Some(ref value) if html::is_url_attr(&attr) && ! self.is_allowed_url(value) => {},
+							Some(value) => { tag.try_push_attr(attr, value); },
+							None => { tag.try_push_attr(attr, ""); },
+						}
+					}
+					if is_void_element(&name) || self_closing {
+						match stack.last_mut() {
+							Some(top) => top.push_tag(tag),
+							None => parent.push_tag(tag),
+						}
+					} else {
+						stack.push(tag);
+					}
+				},
+				Token::EndTag(name) => {
+					if let Some(pos) = stack.iter().rposition(|t| t.name() == name) {
+						while stack.len() > pos {
+							Sanitizer::close(&mut stack, parent);
+						}
+					}
+				},
+				Token::Comment(_) | Token::Doctype(_) => {},
+			}
+		}
+		while ! stack.is_empty() {
+			Sanitizer::close(&mut stack, parent);
+		}
+	}
+
+	pub fn sanitize(&self, input: &str) -> SafeHtml<'static> {
+		let mut root = Tag::new("div");
+		self.sanitize_into(input, &mut root);
+		let mut html = String::new();
+		let _ = root.render_children(&mut html);
+		SafeHtml::trusted(html)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Sanitizer;
+	#[test]
+	fn test_sanitize() {
+		let sanitizer = Sanitizer::basic();
+		assert_eq!("<p>hi <b>there</b></p>", sanitizer.sanitize("<p>hi <b>there</p>").as_str());
+		assert_eq!("x", sanitizer.sanitize("<div onclick=\"alert(1)\">x</div>").as_str());
+		assert_eq!("x", sanitizer.sanitize("<script>alert(1)</script>x").as_str());
+		assert_eq!("<a>x</a><a href=\"/a?b=&lt;\">y</a>", sanitizer.sanitize("<a href=\"javascript:alert(1)\" onclick=x>x</a><a href='/a?b=&lt;'>y</a>").as_str());
+		assert_eq!("<a href=\"https://example.com/\">z</a>", sanitizer.sanitize("<A HREF=\"https://example.com/\">z").as_str());
+		assert_eq!("a &lt; b<br>", sanitizer.sanitize("a &lt; b<br/><!-- x --></p>").as_str());
+		assert_eq!("<a>ftp</a>", sanitizer.sanitize("<a href=\"ftp://x/\">ftp</a>").as_str());
+	}
+	#[test]
+	fn test_sanitize_non_ascii() {
+		let sanitizer = Sanitizer::basic();
+		assert_eq!("<p>あいう</p>", sanitizer.sanitize("<p>あいう</p>").as_str());
+		assert_eq!("<b>日本語</b> &lt; é", sanitizer.sanitize("<b>日本語</b> < é<span>").as_str());
+	}
+}
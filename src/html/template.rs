@@ -0,0 +1,567 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+use html;
+use html::SafeHtml;
+use Response;
+
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub struct TemplateError {
+	pub name: String,
+	pub line: usize,
+	pub message: String,
+}
+
+impl TemplateError {
+	fn new(name: &str, line: usize, message: String) -> TemplateError {
+		TemplateError {
+			name: String::from(name),
+			line: line,
+			message: message,
+		}
+	}
+}
+
+impl fmt::Display for TemplateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.name, self.line, self.message)
+	}
+}
+
+impl error::Error for TemplateError {
+	fn description(&self) -> &str {
+		self.message.as_str()
+	}
+}
+
+#[derive(Clone,Debug)]
+pub enum Value {
+	Str(String),
+	Bool(bool),
+	List(Vec<Value>),
+	Map(BTreeMap<String, Value>),
+	Html(String),
+}
+
+impl Value {
+	fn is_true(&self) -> bool {
+		match *self {
+			Value::Str(ref s) => ! s.is_empty(),
+			Value::Bool(b) => b,
+			Value::List(ref v) => ! v.is_empty(),
+			Value::Map(ref m) => ! m.is_empty(),
+			Value::Html(ref s) => ! s.is_empty(),
+		}
+	}
+}
+
+impl<'a> From<&'a str> for Value {
+	fn from(s: &'a str) -> Value {
+		Value::Str(String::from(s))
+	}
+}
+
+impl From<String> for Value {
+	fn from(s: String) -> Value {
+		Value::Str(s)
+	}
+}
+
+impl From<bool> for Value {
+	fn from(b: bool) -> Value {
+		Value::Bool(b)
+	}
+}
+
+impl From<i64> for Value {
+	fn from(n: i64) -> Value {
+		Value::Str(n.to_string())
+	}
+}
+
+impl From<usize> for Value {
+	fn from(n: usize) -> Value {
+		Value::Str(n.to_string())
+	}
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+	fn from(v: Vec<T>) -> Value {
+		Value::List(v.into_iter().map(|x| x.into()).collect())
+	}
+}
+
+impl<'a> From<SafeHtml<'a>> for Value {
+	fn from(h: SafeHtml<'a>) -> Value {
+		Value::Html(h.into_string())
+	}
+}
+
+impl From<Context> for Value {
+	fn from(c: Context) -> Value {
+		Value::Map(c.values)
+	}
+}
+
+#[derive(Clone,Debug,Default)]
+pub struct Context {
+	values: BTreeMap<String, Value>,
+}
+
+impl Context {
+	pub fn new() -> Context {
+		Context { values: BTreeMap::new() }
+	}
+	pub fn set<T: Into<Value>>(&mut self, name: &str, value: T) {
+		self.values.insert(String::from(name), value.into());
+	}
+}
+
+enum Node {
+	Text(String),
+	Var(Vec<String>, bool, usize),
+	If(Vec<String>, bool, Vec<Node>, Vec<Node>),
+	For(String, Vec<String>, Vec<Node>, usize),
+	Include(String, usize),
+	Block(String, Vec<Node>),
+}
+
+struct Template {
+	extends: Option<(String, usize)>,
+	nodes: Vec<Node>,
+}
+
+enum Token<'a> {
+	Text(&'a str),
+	Var(&'a str, usize),
+	Tag(&'a str, usize),
+}
+
+fn tokenize<'a>(name: &str, source: &'a str) -> Result<Vec<Token<'a>>, TemplateError> {
+	let mut tokens: Vec<Token> = Vec::new();
+	let mut rest = source;
+	let mut line = 1;
+	while let Some(pos) = rest.find('{') {
+		let close = match rest[pos + 1 ..].chars().next() {
+			Some('{') => "}}",
+			Some('%') => "%}",
+			Some('#') => "#}",
+			_ => {
+				tokens.push(Token::Text(&rest[.. pos + 1]));
+				line += rest[.. pos + 1].matches('\n').count();
+				rest = &rest[pos + 1 ..];
+				continue;
+			},
+		};
+		if pos > 0 {
+			tokens.push(Token::Text(&rest[.. pos]));
+			line += rest[.. pos].matches('\n').count();
+		}
+		let inner = &rest[pos + 2 ..];
+		let end = match inner.find(close) {
+			Some(end) => end,
+			None => return Err(TemplateError::new(name, line, format!("unclosed '{}'", &rest[pos .. pos + 2]))),
+		};
+		match close {
+			"}}" => tokens.push(Token::Var(inner[.. end].trim(), line)),
+			"%}" => tokens.push(Token::Tag(inner[.. end].trim(), line)),
+			_ => {},
+		}
+		line += inner[.. end].matches('\n').count();
+		rest = &inner[end + 2 ..];
+	}
+	if ! rest.is_empty() {
+		tokens.push(Token::Text(rest));
+	}
+	Ok(tokens)
+}
+
+fn parse_path(name: &str, line: usize, s: &str) -> Result<Vec<String>, TemplateError> {
+	let path: Vec<String> = s.split('.').map(String::from).collect();
+	for part in &path {
+		if part.is_empty() || ! part.chars().all(|c| c.is_alphanumeric() || c == '_') {
+			return Err(TemplateError::new(name, line, format!("invalid variable '{}'", s)));
+		}
+	}
+	Ok(path)
+}
+
+fn parse_name(name: &str, line: usize, s: &str) -> Result<String, TemplateError> {
+	let s = s.trim();
+	if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+		Ok(String::from(&s[1 .. s.len() - 1]))
+	} else {
+		Err(TemplateError::new(name, line, format!("expected a quoted template name, found '{}'", s)))
+	}
+}
+
+struct Parser<'a, 'b> {
+	name: &'b str,
+	tokens: Vec<Token<'a>>,
+	pos: usize,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+
+	// Parses nodes until one of `ends` is reached, returning that tag.
+	fn parse_nodes(&mut self, ends: &[&str], open: Option<(&str, usize)>) -> Result<(Vec<Node>, Option<&'a str>), TemplateError> {
+		let mut nodes: Vec<Node> = Vec::new();
+		while self.pos < self.tokens.len() {
+			self.pos += 1;
+			match self.tokens[self.pos - 1] {
+				Token::Text(s) => nodes.push(Node::Text(String::from(s))),
+				Token::Var(s, line) => {
+					let (s, safe) = match s.rfind('|') {
+						Some(pos) if s[pos + 1 ..].trim() == "safe" => (s[.. pos].trim(), true),
+						Some(_) => return Err(TemplateError::new(self.name, line, format!("unknown filter in '{}'", s))),
+						None => (s, false),
+					};
+					nodes.push(Node::Var(parse_path(self.name, line, s)?, safe, line));
+				},
+				Token::Tag(s, line) => {
+					let keyword = s.split_whitespace().next().unwrap_or("");
+					let args = s[keyword.len() ..].trim();
+					if ends.contains(&keyword) {
+						return Ok((nodes, Some(keyword)));
+					}
+					match keyword {
+						"if" => {
+							let (negate, cond) = match args.strip_prefix("not ") {
+								Some(cond) => (true, cond.trim()),
+								None => (false, args),
+							};
+							let cond = parse_path(self.name, line, cond)?;
+							let (then, end) = self.parse_nodes(&["else", "endif"], Some(("if", line)))?;
+							let otherwise = if end == Some("else") {
+								self.parse_nodes(&["endif"], Some(("if", line)))?.0
+							} else {
+								Vec::new()
+							};
+							nodes.push(Node::If(cond, negate, then, otherwise));
+						},
+						"for" => {
+							let parts: Vec<&str> = args.split_whitespace().collect();
+							if parts.len() != 3 || parts[1] != "in" {
+								return Err(TemplateError::new(self.name, line, format!("expected 'for x in y', found 'for {}'", args)));
+							}
+							let var = parse_path(self.name, line, parts[0])?;
+							if var.len() != 1 {
+								return Err(TemplateError::new(self.name, line, format!("invalid loop variable '{}'", parts[0])));
+							}
+							let list = parse_path(self.name, line, parts[2])?;
+							let body = self.parse_nodes(&["endfor"], Some(("for", line)))?.0;
+							nodes.push(Node::For(var[0].clone(), list, body, line));
+						},
+						"include" => nodes.push(Node::Include(parse_name(self.name, line, args)?, line)),
+						"block" => {
+							let block = parse_path(self.name, line, args)?;
+							let body = self.parse_nodes(&["endblock"], Some(("block", line)))?.0;
+							nodes.push(Node::Block(block.join("."), body));
+						},
+						"extends" => return Err(TemplateError::new(self.name, line, String::from("'extends' must be the first tag"))),
+						_ => return Err(TemplateError::new(self.name, line, format!("unexpected '{}'", keyword))),
+					}
+				},
+			}
+		}
+		match open {
+			Some((tag, line)) => Err(TemplateError::new(self.name, line, format!("unclosed '{}'", tag))),
+			None => Ok((nodes, None)),
+		}
+	}
+}
+
+fn compile(name: &str, source: &str) -> Result<Template, TemplateError> {
+	let mut tokens = tokenize(name, source)?;
+	let mut extends: Option<(String, usize)> = None;
+	let first = tokens.iter().position(|t| match *t {
+		Token::Text(s) => ! s.trim().is_empty(),
+		_ => true,
+	});
+	if let Some(first) = first {
+		if let Token::Tag(s, line) = tokens[first] {
+			if let Some(parent) = s.strip_prefix("extends ") {
+				extends = Some((parse_name(name, line, parent)?, line));
+				tokens.drain(.. first + 1);
+			}
+		}
+	}
+	let mut parser = Parser { name: name, tokens: tokens, pos: 0 };
+	let nodes = parser.parse_nodes(&[], None)?.0;
+	Ok(Template { extends: extends, nodes: nodes })
+}
+
+// Blocks are kept with the name of the template they come from, so that
+// errors in them point at the right file.
+fn collect_blocks<'a>(source: &'a str, nodes: &'a [Node], blocks: &mut HashMap<&'a str, (&'a str, &'a [Node])>) {
+	for node in nodes {
+		match *node {
+			Node::Block(ref name, ref body) => {
+				blocks.entry(name.as_str()).or_insert((source, body.as_slice()));
+				collect_blocks(source, body, blocks);
+			},
+			Node::If(_, _, ref then, ref otherwise) => {
+				collect_blocks(source, then, blocks);
+				collect_blocks(source, otherwise, blocks);
+			},
+			Node::For(_, _, ref body, _) => collect_blocks(source, body, blocks),
+			_ => {},
+		}
+	}
+}
+
+fn references(nodes: &[Node], result: &mut Vec<(String, usize)>) {
+	for node in nodes {
+		match *node {
+			Node::Include(ref name, line) => result.push((name.clone(), line)),
+			Node::Block(_, ref body) | Node::For(_, _, ref body, _) => references(body, result),
+			Node::If(_, _, ref then, ref otherwise) => {
+				references(then, result);
+				references(otherwise, result);
+			},
+			_ => {},
+		}
+	}
+}
+
+struct Renderer<'a> {
+	templates: &'a Templates,
+	blocks: HashMap<&'a str, (&'a str, &'a [Node])>,
+	scopes: Vec<(&'a str, &'a Value)>,
+	root: &'a Context,
+}
+
+impl<'a> Renderer<'a> {
+
+	fn lookup(&self, path: &[String]) -> Option<&'a Value> {
+		let mut value = match self.scopes.iter().rev().find(|scope| scope.0 == path[0]) {
+			Some(scope) => scope.1,
+			None => self.root.values.get(&path[0])?,
+		};
+		for part in &path[1 ..] {
+			value = match *value {
+				Value::Map(ref map) => map.get(part)?,
+				_ => return None,
+			};
+		}
+		Some(value)
+	}
+
+	fn render(&mut self, name: &str, nodes: &'a [Node], depth: usize, out: &mut String) -> Result<(), TemplateError> {
+		for node in nodes {
+			match *node {
+				Node::Text(ref s) => out.push_str(s.as_str()),
+				Node::Var(ref path, safe, line) => {
+					let (s, safe) = match self.lookup(path) {
+						Some(Value::Str(s)) => (s.as_str(), safe),
+						Some(Value::Html(s)) => (s.as_str(), true),
+						Some(Value::Bool(b)) => (if *b { "true" } else { "false" }, true),
+						Some(_) => return Err(TemplateError::new(name, line, format!("'{}' is not printable", path.join(".")))),
+						None => return Err(TemplateError::new(name, line, format!("'{}' is undefined", path.join(".")))),
+					};
+					if safe {
+						out.push_str(s);
+					} else {
+						out.push_str(html::escape_attr(s).as_str());
+					}
+				},
+				Node::If(ref cond, negate, ref then, ref otherwise) => {
+					let value = self.lookup(cond).is_some_and(|v| v.is_true());
+					self.render(name, if value != negate { then } else { otherwise }, depth, out)?;
+				},
+				Node::For(ref var, ref list, ref body, line) => {
+					let items = match self.lookup(list) {
+						Some(Value::List(items)) => items,
+						None => continue,
+						Some(_) => return Err(TemplateError::new(name, line, format!("'{}' is not a list", list.join(".")))),
+					};
+					for item in items {
+						self.scopes.push((var.as_str(), item));
+						let result = self.render(name, body, depth, out);
+						self.scopes.pop();
+						result?;
+					}
+				},
+				Node::Include(ref include, line) => {
+					if depth >= MAX_DEPTH {
+						return Err(TemplateError::new(name, line, format!("too many nested includes of '{}'", include)));
+					}
+					let template = match self.templates.templates.get(include) {
+						Some(template) => template,
+						None => return Err(TemplateError::new(name, line, format!("template '{}' not found", include))),
+					};
+					self.render(include, template.nodes.as_slice(), depth + 1, out)?;
+				},
+				Node::Block(ref block, ref body) => {
+					match self.blocks.get(block.as_str()).cloned() {
+						Some((source, body)) => self.render(source, body, depth, out)?,
+						None => self.render(name, body.as_slice(), depth, out)?,
+					}
+				},
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Default)]
+pub struct Templates {
+	templates: HashMap<String, Template>,
+}
+
+impl Templates {
+
+	pub fn new() -> Templates {
+		Templates { templates: HashMap::new() }
+	}
+
+	pub fn load(dir: &Path) -> Result<Templates, TemplateError> {
+		let mut templates = Templates::new();
+		templates.load_dir(dir, "")?;
+		templates.check()?;
+		Ok(templates)
+	}
+
+	fn load_dir(&mut self, dir: &Path, prefix: &str) -> Result<(), TemplateError> {
+		let io_error = |e: ::std::io::Error| TemplateError::new(dir.to_str().unwrap_or(""), 0, e.to_string());
+		for entry in fs::read_dir(dir).map_err(&io_error)? {
+			let path = entry.map_err(&io_error)?.path();
+			let file_name = match path.file_name().and_then(|x| x.to_str()) {
+				Some(file_name) => format!("{}{}", prefix, file_name),
+				None => continue,
+			};
+			if path.is_dir() {
+				self.load_dir(&path, format!("{}/", file_name).as_str())?;
+			} else {
+				let mut source = String::new();
+				fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut source)).map_err(&io_error)?;
+				self.push_template(file_name.as_str(), source.as_str())?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn push_template(&mut self, name: &str, source: &str) -> Result<(), TemplateError> {
+		let template = compile(name, source)?;
+		self.templates.insert(String::from(name), template);
+		Ok(())
+	}
+
+	// Checks that every include and extends target exists.
+	pub fn check(&self) -> Result<(), TemplateError> {
+		for (name, template) in self.templates.iter() {
+			let mut refs: Vec<(String, usize)> = Vec::new();
+			if let Some(ref extends) = template.extends {
+				refs.push(extends.clone());
+			}
+			references(template.nodes.as_slice(), &mut refs);
+			for (target, line) in refs {
+				if ! self.templates.contains_key(&target) {
+					return Err(TemplateError::new(name, line, format!("template '{}' not found", target)));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn render(&self, name: &str, context: &Context) -> Result<String, TemplateError> {
+		let (mut current, mut template) = match self.templates.get_key_value(name) {
+			Some((name, template)) => (name.as_str(), template),
+			None => return Err(TemplateError::new(name, 0, String::from("template not found"))),
+		};
+		let mut renderer = Renderer {
+			templates: self,
+			blocks: HashMap::new(),
+			scopes: Vec::new(),
+			root: context,
+		};
+		let mut depth = 0;
+		while let Some((ref parent, line)) = template.extends {
+			if depth >= MAX_DEPTH {
+				return Err(TemplateError::new(current, line, format!("too many nested layouts of '{}'", parent)));
+			}
+			collect_blocks(current, template.nodes.as_slice(), &mut renderer.blocks);
+			template = match self.templates.get(parent) {
+				Some(template) => template,
+				None => return Err(TemplateError::new(current, line, format!("template '{}' not found", parent))),
+			};
+			current = parent.as_str();
+			depth += 1;
+		}
+		let mut out = String::new();
+		renderer.render(current, template.nodes.as_slice(), 0, &mut out)?;
+		Ok(out)
+	}
+
+	pub fn response(&self, name: &str, context: &Context) -> Response {
+		match self.render(name, context) {
+			Ok(html) => Response::from_string(html),
+			Err(_) => Response::from_status(500),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Templates;
+	use super::Context;
+	use html::SafeHtml;
+	fn templates() -> Templates {
+		let mut templates = Templates::new();
+		templates.push_template("layout.html", "<html><title>{% block title %}default{% endblock %}</title>{% block body %}{% endblock %}</html>").unwrap();
+		templates.push_template("item.html", "<li>{{ item.name }}</li>").unwrap();
+		templates.push_template("page.html", "{% extends \"layout.html\" %}\n{% block title %}{{ title }}{% endblock %}\n{% block body %}{% if not items %}empty{% else %}<ul>{% for item in items %}{% include \"item.html\" %}{% endfor %}</ul>{% endif %}{% endblock %}").unwrap();
+		templates.check().unwrap();
+		templates
+	}
+	#[test]
+	fn test_render() {
+		let templates = templates();
+		let mut context = Context::new();
+		context.set("title", "<Hello>");
+		let mut item = Context::new();
+		item.set("name", "a&b");
+		context.set("items", vec![item]);
+		assert_eq!("<html><title>&lt;Hello&gt;</title><ul><li>a&amp;b</li></ul></html>", templates.render("page.html", &context).unwrap());
+		context.set("items", Vec::<String>::new());
+		assert_eq!("<html><title>&lt;Hello&gt;</title>empty</html>", templates.render("page.html", &context).unwrap());
+	}
+	#[test]
+	fn test_errors() {
+		let mut templates = Templates::new();
+		let e = templates.push_template("a.html", "line1\n{% if x %}\nline3").err().unwrap();
+		assert_eq!(2, e.line);
+		let e = templates.push_template("a.html", "line1\n\n{{ x }\n").err().unwrap();
+		assert_eq!(3, e.line);
+		templates.push_template("b.html", "\n{% include \"c.html\" %}").unwrap();
+		let e = templates.check().err().unwrap();
+		assert_eq!("b.html:2: template 'c.html' not found", e.to_string());
+		templates.push_template("d.html", "{{ raw|safe }}\n{{ missing }}").unwrap();
+		let mut context = Context::new();
+		context.set("raw", "<b>");
+		let e = templates.render("d.html", &context).err().unwrap();
+		assert_eq!(2, e.line);
+		context.set("missing", SafeHtml::trusted("<i>'</i>"));
+		assert_eq!("<b>\n<i>'</i>", templates.render("d.html", &context).unwrap());
+		context.set("missing", "<i>'</i>");
+		assert_eq!("<b>\n&lt;i&gt;&#39;&lt;/i&gt;", templates.render("d.html", &context).unwrap());
+	}
+	#[test]
+	fn test_block_errors() {
+		let mut templates = templates();
+		templates.push_template("broken.html", "{% extends \"layout.html\" %}\n{% block title %}x{% endblock %}\n{% block body %}\n\n{{ missing }}{% endblock %}").unwrap();
+		let e = templates.render("broken.html", &Context::new()).err().unwrap();
+		assert_eq!("broken.html:5: 'missing' is undefined", e.to_string());
+		templates.push_template("layout.html", "<html>\n{% block body %}{{ missing }}{% endblock %}</html>").unwrap();
+		templates.push_template("plain.html", "{% extends \"layout.html\" %}").unwrap();
+		let e = templates.render("plain.html", &Context::new()).err().unwrap();
+		assert_eq!("layout.html:2: 'missing' is undefined", e.to_string());
+	}
+}
@@ -0,0 +1,179 @@
+use std::cmp::Reverse;
+
+use http::trim;
+
+// One entry of an `Accept`, `Accept-Language` or `Accept-Charset` header,
+// such as `text/html`, `en-US` or `*`. The quality is the q-value in
+// thousandths, so `q=0.8` is 800 and no q-value at all is 1000.
+#[derive(Debug,PartialEq)]
+pub struct Preference<'a> {
+	pub value: &'a [u8],
+	pub quality: u16,
+}
+
+fn parse_quality(value: &[u8]) -> Option<u16> {
+	let (int, frac) = match value.iter().position(|&x| x == b'.') {
+		Some(pos) => (&value[.. pos], &value[pos + 1 ..]),
+		None => (value, &b""[..]),
+	};
+	if frac.len() > 3 || ! frac.iter().all(|x| x.is_ascii_digit()) {
+		return None
+	}
+	let thousandths = frac.iter().chain(b"000".iter()).take(3).fold(0, |a, &x| a * 10 + (x - b'0') as u16);
+	match int {
+		b"0" => Some(thousandths),
+		b"1" if thousandths == 0 => Some(1000),
+		_ => None,
+	}
+}
+
+// Parses a header value into its entries, most preferred first. Entries
+// with a malformed q-value are left out, and parameters other than q are
+// dropped.
+pub fn parse(header: &[u8]) -> Vec<Preference<'_>> {
+	let mut prefs = Vec::new();
+	for item in header.split(|&x| x == b',') {
+		let mut params = item.split(|&x| x == b';').map(trim);
+		let value = match params.next() {
+			Some(v) if ! v.is_empty() => v,
+			_ => continue,
+		};
+		let mut quality = Some(1000);
+		for param in params {
+			if param.len() > 2 && param[.. 2].eq_ignore_ascii_case(b"q=") {
+				quality = parse_quality(&param[2 ..]);
+			}
+		}
+		if let Some(q) = quality {
+			prefs.push(Preference { value: value, quality: q });
+		}
+	}
+	// A stable sort keeps the client's order between equal qualities.
+	prefs.sort_by_key(|p| Reverse(p.quality));
+	prefs
+}
+
+// How specifically a range matches a value, or None if it doesn't.
+type Matcher = fn(&[u8], &[u8]) -> Option<usize>;
+
+fn split_media(media: &[u8]) -> (&[u8], &[u8]) {
+	match media.iter().position(|&x| x == b'/') {
+		Some(pos) => (&media[.. pos], &media[pos + 1 ..]),
+		None => (media, &b""[..]),
+	}
+}
+
+pub fn match_media(range: &[u8], media: &[u8]) -> Option<usize> {
+	let (range_type, range_sub) = split_media(range);
+	let (media_type, media_sub) = split_media(media);
+	if range_type == b"*" && range_sub == b"*" {
+		Some(0)
+	} else if ! range_type.eq_ignore_ascii_case(media_type) {
+		None
+	} else if range_sub == b"*" {
+		Some(1)
+	} else if range_sub.eq_ignore_ascii_case(media_sub) {
+		Some(2)
+	} else {
+		None
+	}
+}
+
+// Basic filtering from RFC 4647: `en` matches `en` and `en-US`.
+pub fn match_language(range: &[u8], tag: &[u8]) -> Option<usize> {
+	if range == b"*" {
+		Some(0)
+	} else if range.eq_ignore_ascii_case(tag) || (tag.len() > range.len() && tag[range.len()] == b'-' && tag[.. range.len()].eq_ignore_ascii_case(range)) {
+		Some(range.len())
+	} else {
+		None
+	}
+}
+
+pub fn match_charset(range: &[u8], charset: &[u8]) -> Option<usize> {
+	if range == b"*" {
+		Some(0)
+	} else if range.eq_ignore_ascii_case(charset) {
+		Some(1)
+	} else {
+		None
+	}
+}
+
+// The quality of a value under the most specific range matching it. A
+// missing header accepts everything.
+pub fn quality(prefs: Option<&[Preference]>, value: &[u8], matcher: Matcher) -> u16 {
+	let prefs = match prefs {
+		Some(p) => p,
+		None => return 1000,
+	};
+	let mut best: Option<(usize, u16)> = None;
+	for pref in prefs {
+		if let Some(specificity) = matcher(pref.value, value) {
+			if best.is_none_or(|b| specificity > b.0) {
+				best = Some((specificity, pref.quality));
+			}
+		}
+	}
+	best.map_or(0, |b| b.1)
+}
+
+// Picks the offer with the highest quality, preferring earlier offers on a
+// tie, or None if the client accepts none of them.
+pub fn negotiate<'b>(header: Option<&[u8]>, offers: &[&'b str], matcher: Matcher) -> Option<&'b str> {
+	let prefs = header.map(parse);
+	let mut best: Option<(&'b str, u16)> = None;
+	for &offer in offers {
+		let q = quality(prefs.as_deref(), offer.as_bytes(), matcher);
+		if q > 0 && best.is_none_or(|b| q > b.1) {
+			best = Some((offer, q));
+		}
+	}
+	best.map(|b| b.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Preference;
+	use super::match_charset;
+	use super::match_language;
+	use super::match_media;
+	use super::negotiate;
+	use super::parse;
+	use http::with_request;
+	#[test]
+	fn test_parse() {
+		assert_eq!(vec![
+			Preference { value: b"text/html", quality: 1000 },
+			Preference { value: b"application/xml", quality: 900 },
+			Preference { value: b"*/*", quality: 800 },
+			Preference { value: b"text/plain", quality: 0 },
+		], parse(b"application/xml;q=0.9, text/html, text/plain; Q=0, */*;q=0.8, image/png;q=2, image/gif;q=0.1234,"));
+		assert_eq!(vec![Preference { value: b"text/html", quality: 500 }], parse(b"text/html;level=1;q=0.5"));
+	}
+	#[test]
+	fn test_negotiate() {
+		let accept = Some(&b"text/html;q=0.9, application/*;q=0.5, */*;q=0.1, text/csv;q=0"[..]);
+		assert_eq!(Some("text/html"), negotiate(accept, &["application/json", "text/html"], match_media));
+		assert_eq!(Some("application/json"), negotiate(accept, &["application/json", "image/png"], match_media));
+		assert_eq!(None, negotiate(accept, &["text/csv"], match_media));
+		assert_eq!(None, negotiate(Some(b"text/html"), &["application/json"], match_media));
+		assert_eq!(Some("application/json"), negotiate(None, &["application/json", "text/html"], match_media));
+		let lang = Some(&b"en-US,en;q=0.8,ja;q=0.5"[..]);
+		assert_eq!(Some("en"), negotiate(lang, &["ja", "en"], match_language));
+		assert_eq!(Some("ja"), negotiate(lang, &["fr", "ja"], match_language));
+		assert_eq!(Some("en-GB"), negotiate(lang, &["fr", "ja", "en-GB"], match_language));
+		assert_eq!(None, negotiate(Some(b"en"), &["english"], match_language));
+		assert_eq!(Some("UTF-8"), negotiate(Some(b"iso-8859-1;q=0.5, utf-8"), &["iso-8859-1", "UTF-8"], match_charset));
+		assert_eq!(Some("Shift_JIS"), negotiate(Some(b"utf-8;q=0, *;q=0.1"), &["utf-8", "Shift_JIS"], match_charset));
+	}
+	#[test]
+	fn test_request() {
+		with_request(&["GET / HTTP/1.1", "Accept: application/json", "Accept-Charset: utf-8"], None, |req| {
+			assert_eq!(Some("application/json"), req.negotiate(&["text/html", "application/json"]).ok());
+			assert_eq!(406, req.negotiate(&["text/html"]).err().unwrap().status());
+			assert_eq!(Some("utf-8"), req.negotiate_charset(&["utf-8"]));
+			assert_eq!(Some("en"), req.negotiate_language(&["en"]));
+		});
+	}
+}
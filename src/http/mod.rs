@@ -0,0 +1,932 @@
+extern crate time;
+
+use std::ascii::AsciiExt;
+use std::io::ErrorKind;
+use std::io::prelude::*;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::str::from_utf8;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use Handler;
+use Request;
+use Response;
+use Params;
+
+use url::Uri;
+use log::AccessLog;
+use log::LogEntry;
+use metrics::Metrics;
+use http::parser::HeadParser;
+
+pub mod accept;
+pub mod multipart;
+pub mod parser;
+pub mod server;
+
+const LF: u8 = 10;
+const CR: u8 = 13;
+const SP: u8 = 32;
+
+#[derive(Copy,Clone)]
+pub enum Protocol {
+	Http10,
+	Http11,
+}
+
+#[derive(Copy,Clone)]
+pub enum Method {
+	GET,
+	POST,
+	OPTIONS,
+}
+
+impl Method {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			Method::GET => "GET",
+			Method::POST => "POST",
+			Method::OPTIONS => "OPTIONS",
+		}
+	}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Expect {
+	Continue,
+	Unknown,
+}
+
+#[derive(Copy,Clone)]
+pub enum Connection {
+	Close,
+	KeepAlive,
+}
+
+pub fn reason_phrase(status: u32) -> Option<&'static str> {
+	match status {
+		100 => Some("Continue"),
+		200 => Some("OK"),
+		201 => Some("Created"),
+		202 => Some("Accepted"),
+		204 => Some("No Content"),
+		301 => Some("Moved Permanently"),
+		302 => Some("Found"),
+		303 => Some("See Other"),
+		304 => Some("Not Modified"),
+		307 => Some("Temporary Redirect"),
+		308 => Some("Permanent Redirect"),
+		400 => Some("Bad Request"),
+		401 => Some("Unauthorized"),
+		403 => Some("Forbidden"),
+		404 => Some("Not Found"),
+		405 => Some("Method Not Allowed"),
+		406 => Some("Not Acceptable"),
+		408 => Some("Request Timeout"),
+		411 => Some("Length Required"),
+		412 => Some("Precondition Failed"),
+		413 => Some("Payload Too Large"),
+		414 => Some("URI Too Long"),
+		415 => Some("Unsupported Media Type"),
+		417 => Some("Expectation Failed"),
+		429 => Some("Too Many Requests"),
+		431 => Some("Request Header Fields Too Large"),
+		500 => Some("Internal Server Error"),
+		501 => Some("Not Implemented"),
+		503 => Some("Service Unavailable"),
+		_ => None,
+	}
+}
+
+pub enum PostData<'a> {
+	None,
+	Buf(&'a [u8]),
+	Vec(Vec<u8>),
+}
+
+fn trim(str: &[u8]) -> &[u8] {
+	if let Some(pos1) = str.iter().position(|&x| x != SP) {
+		if let Some(pos2) = str.iter().rposition(|&x| x != SP) {
+			return &str[pos1 .. pos2 + 1]
+		}
+	}
+	str
+}
+
+pub struct Header<'a> {
+	pub lines: Vec<&'a [u8]>,
+}
+
+impl<'a> Header<'a> {
+	// The size on the wire, assuming CRLF line endings.
+	fn size(&self) -> usize {
+		self.lines.iter().fold(2, |a, l| a + l.len() + 2)
+	}
+	pub fn get_string(&self, name: &[u8]) -> Option<&[u8]> {
+		for line in &self.lines {
+			if line.len() > name.len() {
+				if line[.. name.len()].eq_ignore_ascii_case(name) && line[name.len()] == b':' {
+					return Some(trim(&line[name.len() + 1 ..]))
+				}
+			}
+		}
+		None
+	}
+	// Every value of a header that may be sent on several lines, in order.
+	pub fn get_strings(&self, name: &[u8]) -> Vec<&[u8]> {
+		self.lines.iter()
+			.filter(|line| line.len() > name.len() && line[.. name.len()].eq_ignore_ascii_case(name) && line[name.len()] == b':')
+			.map(|line| trim(&line[name.len() + 1 ..]))
+			.collect()
+	}
+	// Anything but digits, or a value too large, counts as missing, so
+	// callers should check `get_string` to tell a bad value apart.
+	fn get_number(&self, name: &[u8]) -> Option<usize> {
+		let value = self.get_string(name)?;
+		if value.is_empty() {
+			return None
+		}
+		value.iter().try_fold(0usize, |a, &x|
+			if x.is_ascii_digit() { a.checked_mul(10)?.checked_add((x - b'0') as usize) } else { None }
+		)
+	}
+	pub fn protocol(&self) -> Option<Protocol> {
+		if let Some(line) = self.lines.get(0) {
+			if let Some(pos) = line.iter().rposition(|&x| x == SP) {
+				if line[pos + 1 ..].eq_ignore_ascii_case(b"HTTP/1.0") {
+					return Some(Protocol::Http10);
+				} else if line[pos + 1 ..].eq_ignore_ascii_case(b"HTTP/1.1") {
+					return Some(Protocol::Http11);
+				}
+			}
+		}
+		None
+	}
+	pub fn method(&self) -> Option<Method> {
+		if let Some(line) = self.lines.get(0) {
+			if let Some(pos) = line.iter().position(|&x| x == SP) {
+				if line[.. pos].eq_ignore_ascii_case(b"GET") {
+					return Some(Method::GET);
+				} else if line[.. pos].eq_ignore_ascii_case(b"POST") {
+					return Some(Method::POST);
+				} else if line[.. pos].eq_ignore_ascii_case(b"OPTIONS") {
+					return Some(Method::OPTIONS);
+				}
+			}
+		}
+		None
+	}
+	pub fn path(&self) -> Option<&[u8]> {
+		if let Some(line) = self.lines.get(0) {
+			if let Some(pos1) = line.iter().position(|&x| x == SP) {
+				if let Some(pos2) = line.iter().rposition(|&x| x == SP) {
+					if pos1 + 1 < pos2 {
+						return Some(&line[pos1 + 1 .. pos2])
+					}
+				}
+			}
+		}
+		None
+	}
+	pub fn uri(&self) -> Option<Uri> {
+		self.path().and_then(Uri::parse)
+	}
+	pub fn connection(&self) -> Option<Connection> {
+		if let Some(value) = self.get_string(b"Connection") {
+			if value.eq_ignore_ascii_case(b"keep-alive") {
+				return Some(Connection::KeepAlive);
+			} else if value.eq_ignore_ascii_case(b"close") {
+				return Some(Connection::Close);
+			}
+		}
+		None
+	}
+	pub fn content_length(&self) -> Option<usize> {
+		self.get_number(b"Content-Length")
+	}
+	// Whether `Content-Length` is present but not a number that fits.
+	pub fn bad_content_length(&self) -> bool {
+		self.get_string(b"Content-Length").is_some() && self.content_length().is_none()
+	}
+	pub fn expect(&self) -> Option<Expect> {
+		self.get_string(b"Expect").map(|value| {
+			if value.eq_ignore_ascii_case(b"100-continue") { Expect::Continue } else { Expect::Unknown }
+		})
+	}
+	pub fn host(&self) -> Option<&[u8]> {
+		self.get_string(b"Host")
+	}
+	pub fn cookie(&self, name: &[u8]) -> Option<&[u8]> {
+		for line in &self.lines {
+			if line.len() > 7 && line[.. 7].eq_ignore_ascii_case(b"Cookie:") {
+				for pair in line[7 ..].split(|&x| x == b';') {
+					let pair = trim(pair);
+					if pair.len() > name.len() && pair.starts_with(name) && pair[name.len()] == b'=' {
+						return Some(&pair[name.len() + 1 ..]);
+					}
+				}
+			}
+		}
+		None
+	}
+}
+
+// Counts a connection as open until dropped, even by a panicking handler.
+struct OpenConnection(Arc<Metrics>);
+
+impl OpenConnection {
+	fn new(metrics: Arc<Metrics>) -> OpenConnection {
+		metrics.open_connection();
+		OpenConnection(metrics)
+	}
+}
+
+impl Drop for OpenConnection {
+	fn drop(&mut self) {
+		self.0.close_connection();
+	}
+}
+
+struct RequestImpl<'a> {
+	peer_addr: Option<SocketAddr>,
+	header: &'a Header<'a>,
+	post_data: &'a PostData<'a>,
+}
+
+impl<'a> Request for RequestImpl<'a> {
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		self.peer_addr
+	}
+	fn protocol(&self) -> Option<Protocol> {
+		self.header.protocol()
+	}
+	fn method(&self) -> Option<Method> {
+		self.header.method()
+	}
+	fn path(&self) -> Option<&[u8]> {
+		self.header.path()
+	}
+	fn uri(&self) -> Option<Uri> {
+		self.header.uri()
+	}
+	fn connection(&self) -> Option<Connection> {
+		self.header.connection()
+	}
+	fn content_length(&self) -> Option<usize> {
+		self.header.content_length()
+	}
+	fn host(&self) -> Option<&[u8]> {
+		self.header.host()
+	}
+	fn post_data(&self) -> Option<&[u8]> {
+		match *self.post_data {
+			PostData::None => Option::None,
+			PostData::Buf(slice) => Some(slice),
+			PostData::Vec(ref vec) => Some(vec.as_slice()),
+		}
+	}
+	fn header(&self) -> &Header<'_> {
+		self.header
+	}
+	fn get_params(&self) -> Params {
+		if let Some(path) = self.path() {
+			if let Some(pos) = path.iter().position(|&x| x == b'?') {
+				return Params { query: Some(&path[pos + 1 ..]) }
+			}
+		}
+		Params { query: None }
+	}
+	fn post_params(&self) -> Params {
+		Params { query: self.post_data() }
+	}
+	fn attr(&self, _: &str) -> Option<&str> {
+		None
+	}
+}
+
+// Builds a request from raw header lines and a body for unit tests.
+#[cfg(test)]
+pub fn with_request<F: FnOnce(&Request) -> R, R>(lines: &[&str], body: Option<&[u8]>, f: F) -> R {
+	let header = Header { lines: lines.iter().map(|x| x.as_bytes()).collect() };
+	let post_data = match body {
+		Some(body) => PostData::Buf(body),
+		None => PostData::None,
+	};
+	let request = RequestImpl {
+		peer_addr: "127.0.0.1:50000".parse().ok(),
+		header: &header,
+		post_data: &post_data,
+	};
+	f(&request)
+}
+
+const WEEK: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTH: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Formats a time as an HTTP-date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_date(t: SystemTime) -> String {
+	let secs = match t.duration_since(UNIX_EPOCH) {
+		Ok(d) => d.as_secs() as i64,
+		Err(e) => -(e.duration().as_secs() as i64),
+	};
+	let tm = time::at_utc(time::Timespec::new(secs, 0));
+	format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+		WEEK[tm.tm_wday as usize], tm.tm_mday, MONTH[tm.tm_mon as usize],
+		1900 + tm.tm_year, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+// Days from 1970-01-01 to a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = if y >= 0 { y } else { y - 399 } / 400;
+	let yoe = y - era * 400;
+	let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+// Parses an HTTP-date in any of the three forms allowed by RFC 7231:
+// `Sun, 06 Nov 1994 08:49:37 GMT`, `Sunday, 06-Nov-94 08:49:37 GMT` and
+// the asctime form `Sun Nov  6 08:49:37 1994`. The weekday isn't checked.
+pub fn parse_date(value: &[u8]) -> Option<SystemTime> {
+	let value = from_utf8(value).ok()?;
+	let tokens: Vec<&str> = value.split(&[' ', ',', '-'][..]).filter(|t| ! t.is_empty()).collect();
+	let (day, month, year, clock) = match tokens.len() {
+		6 if tokens[5] == "GMT" => (tokens[1], tokens[2], tokens[3], tokens[4]),
+		5 => (tokens[2], tokens[1], tokens[4], tokens[3]),
+		_ => return None,
+	};
+	let number = |s: &str, digits: &[usize]| -> Option<i64> {
+		if digits.contains(&s.len()) && s.bytes().all(|x| x.is_ascii_digit()) { s.parse().ok() } else { None }
+	};
+	let day = number(day, &[1, 2])?;
+	let month = MONTH.iter().position(|&m| m == month)? as i64 + 1;
+	let year = match year.len() {
+		// Two digit years from RFC 850 are taken to be in 1970..2069.
+		2 => number(year, &[2]).map(|y| if y < 70 { 2000 + y } else { 1900 + y })?,
+		_ => number(year, &[4])?,
+	};
+	let mut clock = clock.split(':').map(|s| number(s, &[2]));
+	let (hour, min, sec) = match (clock.next(), clock.next(), clock.next(), clock.next()) {
+		(Some(Some(h)), Some(Some(m)), Some(Some(s)), None) => (h, m, s),
+		_ => return None,
+	};
+	if ! (1 ..= 31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+		return None
+	}
+	let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + min * 60 + sec;
+	if secs < 0 {
+		return None
+	}
+	Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+// The status code and reason phrase, which is left empty for codes not
+// listed. Only a code that isn't three digits becomes a 500.
+fn status_line(status: u32) -> String {
+	match reason_phrase(status) {
+		Some(reason) => format!("{} {}", status, reason),
+		None if (100 .. 1000).contains(&status) => format!("{} ", status),
+		None => String::from("500 Internal Server Error"),
+	}
+}
+
+fn response_head(protocol: Option<Protocol>, response: &Response) -> String {
+	let mut buf = String::new();
+
+	buf.push_str(match protocol {
+		Some(Protocol::Http10) => "HTTP/1.0",
+		Some(Protocol::Http11) => "HTTP/1.1",
+		_ => "HTTP/1.1",
+	});
+
+	buf.push_str(" ");
+	buf.push_str(status_line(response.status).as_str());
+	buf.push_str("\r\n");
+
+	buf.push_str("Date: ");
+	buf.push_str(format_date(SystemTime::now()).as_str());
+	buf.push_str("\r\n");
+
+	buf.push_str("Server: Akasabi 0.1.0 (Rust 1.16.0)\r\n");
+
+	for (name, value) in response.headers() {
+		buf.push_str(name.as_str());
+		buf.push_str(": ");
+		buf.push_str(value.as_str());
+		buf.push_str("\r\n");
+	}
+
+	if let Some(ref content) = response.content {
+		if response.get_header("Content-Type").is_none() {
+			buf.push_str("Content-Type: text/html; charset=UTF-8\r\n");
+		}
+		buf.push_str("Content-Length: ");
+		buf.push_str(content.len().to_string().as_str());
+		buf.push_str("\r\n");
+	}
+
+	buf.push_str("Connection: ");
+	buf.push_str(match response.connection() {
+		Connection::Close => "close",
+		Connection::KeepAlive => "keep-alive",
+	});
+	buf.push_str("\r\n");
+	buf.push_str("\r\n");
+	buf
+}
+
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+// An empty response that closes the connection.
+fn error_response(status: u32) -> String {
+	format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status_line(status))
+}
+
+#[derive(Clone)]
+pub struct ServerConfig {
+	max_header_size: usize,
+	max_header_count: usize,
+	max_body_size: usize,
+	header_timeout: Option<Duration>,
+	body_timeout: Option<Duration>,
+	write_timeout: Option<Duration>,
+	access_log: Option<Arc<AccessLog>>,
+	metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for ServerConfig {
+	fn default() -> ServerConfig {
+		ServerConfig::new()
+	}
+}
+
+impl ServerConfig {
+
+	pub fn new() -> ServerConfig {
+		ServerConfig {
+			max_header_size: 8192,
+			max_header_count: 100,
+			max_body_size: 65536,
+			header_timeout: Some(Duration::from_secs(30)),
+			body_timeout: Some(Duration::from_secs(60)),
+			write_timeout: Some(Duration::from_secs(60)),
+			access_log: None,
+			metrics: None,
+		}
+	}
+
+	// Limits the request line and all header lines together.
+	pub fn set_max_header_size(&mut self, size: usize) {
+		self.max_header_size = size;
+	}
+	pub fn set_max_header_count(&mut self, count: usize) {
+		self.max_header_count = count;
+	}
+	pub fn set_max_body_size(&mut self, size: usize) {
+		self.max_body_size = size;
+	}
+	// The whole header must arrive within this time, so a client sending
+	// it a byte at a time cannot hold the connection open.
+	pub fn set_header_timeout(&mut self, timeout: Option<Duration>) {
+		self.header_timeout = timeout;
+	}
+	pub fn set_body_timeout(&mut self, timeout: Option<Duration>) {
+		self.body_timeout = timeout;
+	}
+	pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+		self.write_timeout = timeout;
+	}
+	// Clones of this config share the log.
+	pub fn set_access_log(&mut self, log: AccessLog) {
+		self.access_log = Some(Arc::new(log));
+	}
+	pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+		self.metrics = Some(metrics);
+	}
+
+	fn log(&self, peer_addr: Option<SocketAddr>, header: Option<&Header>, status: u32, bytes: usize, start: Instant) {
+		if let Some(ref log) = self.access_log {
+			log.log(&LogEntry {
+				peer_addr: peer_addr,
+				time: time::now_utc(),
+				request_line: header.and_then(|h| h.lines.first()).cloned(),
+				status: status,
+				bytes: bytes,
+				referer: header.and_then(|h| h.get_string(b"Referer")),
+				user_agent: header.and_then(|h| h.get_string(b"User-Agent")),
+				duration: start.elapsed(),
+			});
+		}
+	}
+
+	fn count(&self, header: Option<&Header>, status: u32, received: usize, sent: usize, start: Instant) {
+		if let Some(ref metrics) = self.metrics {
+			let method = header.and_then(|h| h.method()).map_or("OTHER", |m| m.as_str());
+			metrics.request(method, status, received, sent, start.elapsed());
+		}
+	}
+
+	fn parse_error(&self) {
+		if let Some(ref metrics) = self.metrics {
+			metrics.parse_error();
+		}
+	}
+}
+
+enum ReadError {
+	Closed,
+	TooLarge,
+	TimedOut,
+	Failed,
+}
+
+fn read_until(stream: &mut TcpStream, buf: &mut [u8], deadline: Option<Instant>) -> Result<usize, ReadError> {
+	if let Some(deadline) = deadline {
+		let now = Instant::now();
+		if now >= deadline {
+			return Err(ReadError::TimedOut);
+		}
+		if stream.set_read_timeout(Some(deadline - now)).is_err() {
+			return Err(ReadError::Failed);
+		}
+	}
+	loop {
+		match stream.read(buf) {
+			Ok(0) => return Err(ReadError::Closed),
+			Ok(size) => return Ok(size),
+			Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+			Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+				return Err(ReadError::TimedOut);
+			},
+			Err(_) => return Err(ReadError::Failed),
+		}
+	}
+}
+
+pub struct HttpHandler<T> {
+	handler: T,
+	config: ServerConfig,
+	parser: HeadParser,
+	// Bytes read but not yet consumed are `buffer[.. filled]`.
+	filled: usize,
+	buffer: Vec<u8>,
+}
+
+impl<T: Handler> HttpHandler<T> {
+
+	pub fn new(h: T) -> HttpHandler<T> {
+		HttpHandler::with_config(h, ServerConfig::new())
+	}
+
+	pub fn with_config(h: T, config: ServerConfig) -> HttpHandler<T> {
+		let size = config.max_header_size;
+		HttpHandler {
+			handler: h,
+			config: config,
+			parser: HeadParser::new(),
+			filled: 0,
+			buffer: vec![0; size],
+		}
+	}
+
+	// Answers with an empty error response and closes the connection.
+	fn reject(&self, stream: &mut TcpStream, status: u32, header: Option<&Header>, start: Instant) {
+		let response = error_response(status);
+		let _ = stream.write(response.as_bytes());
+		let _ = stream.flush();
+		let _ = stream.shutdown(Shutdown::Both);
+		self.config.log(stream.peer_addr().ok(), header, status, 0, start);
+		self.config.count(header, status, header.map_or(self.filled, |h| h.size()), response.len(), start);
+	}
+
+	fn respond(&self, stream: &mut TcpStream, request: &RequestImpl, response: &Response, received: usize, start: Instant) {
+		let buf = response_head(request.protocol(), response);
+
+		let _ = stream.write(buf.as_bytes());
+
+		if let Some(ref content) = response.content {
+			let _ = stream.write(content.as_slice());
+		}
+
+		let content_length = response.content.as_ref().map_or(0, |c| c.len());
+		self.config.log(request.peer_addr, Some(request.header), response.status, content_length, start);
+		self.config.count(Some(request.header), response.status, received, buf.len() + content_length, start);
+	}
+
+	// Lets the handler look at the header before the client sends the body.
+	// Returns false if it rejected the request and the connection is closed.
+	fn send_continue(&self, stream: &mut TcpStream, peer_addr: Option<SocketAddr>, start: Instant) -> bool {
+		let header = self.parser.header(&self.buffer);
+		let request = RequestImpl {
+			peer_addr: peer_addr,
+			header: &header,
+			post_data: &PostData::None,
+		};
+		match self.handler.expect_continue(&request as &Request) {
+			Some(response) => {
+				self.respond(stream, &request, &response, header.size(), start);
+				let _ = stream.flush();
+				let _ = stream.shutdown(Shutdown::Both);
+				false
+			},
+			None => {
+				let _ = stream.write(CONTINUE);
+				true
+			},
+		}
+	}
+
+	// Reads until the buffer holds a whole header and returns its length.
+	// The buffer is as large as the largest header allowed.
+	fn read_head(&mut self, stream: &mut TcpStream) -> Result<usize, ReadError> {
+		let deadline = self.config.header_timeout.map(|t| Instant::now() + t);
+		loop {
+			let end = self.parser.advance(&self.buffer[.. self.filled]);
+			if self.parser.lines() > self.config.max_header_count + 1 {
+				return Err(ReadError::TooLarge);
+			}
+			if let Some(end) = end {
+				return Ok(end);
+			}
+			if self.filled == self.buffer.len() {
+				return Err(ReadError::TooLarge);
+			}
+			self.filled += match read_until(stream, &mut self.buffer[self.filled ..], deadline) {
+				// Nothing received yet, so the connection was just idle.
+				Err(ReadError::TimedOut) if self.filled == 0 => return Err(ReadError::Closed),
+				result => result?,
+			};
+		}
+	}
+
+	// Reads until the body follows the header in the buffer, growing the
+	// buffer if the body does not fit.
+	fn read_body(&mut self, stream: &mut TcpStream, end: usize) -> Result<(), ReadError> {
+		let deadline = self.config.body_timeout.map(|t| Instant::now() + t);
+		if self.buffer.len() < end {
+			self.buffer.resize(end, 0);
+		}
+		while self.filled < end {
+			self.filled += read_until(stream, &mut self.buffer[self.filled ..], deadline)?;
+		}
+		Ok(())
+	}
+
+	pub fn handle(&mut self, stream: TcpStream) {
+		let _open = self.config.metrics.clone().map(OpenConnection::new);
+		self.serve(stream);
+	}
+
+	fn serve(&mut self, mut stream: TcpStream) {
+
+		let _ = stream.set_write_timeout(self.config.write_timeout);
+
+		loop {
+			self.parser.reset();
+
+			let peer_addr = stream.peer_addr().ok();
+			let mut start = Instant::now();
+
+			let head = match self.read_head(&mut stream) {
+				Ok(head) => head,
+				Err(ReadError::TooLarge) => {
+					self.config.parse_error();
+					self.reject(&mut stream, 431, None, start);
+					return;
+				},
+				Err(ReadError::TimedOut) => {
+					self.reject(&mut stream, 408, None, start);
+					return;
+				},
+				Err(_) => {
+					let _ = stream.shutdown(Shutdown::Both);
+					return;
+				},
+			};
+			start = Instant::now();
+
+			let (method, protocol, content_length, bad_length, expect) = {
+				let header = self.parser.header(&self.buffer);
+				(header.method(), header.protocol(), header.content_length(), header.bad_content_length(), header.expect())
+			};
+
+			if method.is_none() || protocol.is_none() {
+				self.config.parse_error();
+				self.reject(&mut stream, 501, Some(&self.parser.header(&self.buffer)), start);
+				return;
+			}
+
+			if bad_length {
+				self.config.parse_error();
+				self.reject(&mut stream, 400, Some(&self.parser.header(&self.buffer)), start);
+				return;
+			}
+
+			if expect == Some(Expect::Unknown) {
+				self.reject(&mut stream, 417, Some(&self.parser.header(&self.buffer)), start);
+				return;
+			}
+
+			let mut length = None;
+			if let Some(Method::POST) = method {
+				match content_length {
+					Some(size) if size > self.config.max_body_size => {
+						self.reject(&mut stream, 413, Some(&self.parser.header(&self.buffer)), start);
+						return;
+					},
+					Some(size) => {
+						if expect == Some(Expect::Continue) && self.filled < head + size && ! self.send_continue(&mut stream, peer_addr, start) {
+							return;
+						}
+						match self.read_body(&mut stream, head + size) {
+							Ok(()) => length = Some(size),
+							Err(ReadError::TimedOut) => {
+								self.reject(&mut stream, 408, Some(&self.parser.header(&self.buffer)), start);
+								return;
+							},
+							Err(_) => {
+								let _ = stream.shutdown(Shutdown::Both);
+								return;
+							},
+						}
+					},
+					None => {
+						self.reject(&mut stream, 501, Some(&self.parser.header(&self.buffer)), start);
+						return;
+					},
+				}
+			}
+			let consumed = head + length.unwrap_or(0);
+
+			let close = {
+				let header = self.parser.header(&self.buffer);
+				let post_data = match length {
+					Some(length) => PostData::Buf(&self.buffer[head .. head + length]),
+					None => PostData::None,
+				};
+
+				let request = RequestImpl {
+					peer_addr: peer_addr,
+					header: &header,
+					post_data: &post_data,
+				};
+
+				let response = self.handler.handle(&request as &Request);
+
+				self.respond(&mut stream, &request, &response, consumed, start);
+
+				match response.connection() {
+					Connection::Close => true,
+					Connection::KeepAlive => false,
+				}
+			};
+
+			if close {
+				let _ = stream.flush();
+				let _ = stream.shutdown(Shutdown::Both);
+				break;
+			}
+
+			// Keep any pipelined bytes for the next request.
+			self.buffer.copy_within(consumed .. self.filled, 0);
+			self.filled -= consumed;
+			if self.buffer.len() > self.config.max_header_size {
+				self.buffer.truncate(self.config.max_header_size);
+				self.buffer.shrink_to_fit();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::prelude::*;
+	use std::net::TcpListener;
+	use std::net::TcpStream;
+	use std::sync::Arc;
+	use std::thread;
+	use std::time::Duration;
+	use std::time::UNIX_EPOCH;
+	use super::HttpHandler;
+	use super::ServerConfig;
+	use super::format_date;
+	use super::parse_date;
+	use super::response_head;
+	use metrics::Metrics;
+	use Handler;
+	use Request;
+	use Response;
+	struct Echo;
+	impl Handler for Echo {
+		fn handle(&self, req: &Request) -> Response {
+			if req.path() == Some(b"/panic") {
+				panic!("handler failed");
+			}
+			Response::from_str(::std::str::from_utf8(req.post_data().unwrap_or(b"")).unwrap())
+		}
+		fn expect_continue(&self, req: &Request) -> Option<Response> {
+			match req.header().get_string(b"Authorization") {
+				Some(_) => None,
+				None => Some(Response::from_status(401)),
+			}
+		}
+	}
+	fn connect(config: ServerConfig) -> (TcpStream, thread::JoinHandle<()>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			HttpHandler::with_config(Echo, config).handle(stream);
+		});
+		(TcpStream::connect(addr).unwrap(), server)
+	}
+	fn exchange(config: ServerConfig, input: &[u8]) -> String {
+		let (mut stream, server) = connect(config);
+		stream.write_all(input).unwrap();
+		let mut output = String::new();
+		let _ = stream.read_to_string(&mut output);
+		drop(stream);
+		server.join().unwrap();
+		output
+	}
+	#[test]
+	fn test_limits() {
+		let mut config = ServerConfig::new();
+		config.set_max_header_count(2);
+		config.set_max_body_size(4);
+		config.set_header_timeout(Some(Duration::from_millis(100)));
+		config.set_body_timeout(Some(Duration::from_millis(100)));
+		assert!(exchange(config.clone(), b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nConnection: close\r\n\r\nabcd").starts_with("HTTP/1.1 431 "));
+		let ok = exchange(config.clone(), b"POST / HTTP/1.0\r\nContent-Length: 4\r\n\r\nabcd");
+		assert!(ok.starts_with("HTTP/1.0 200 OK\r\n") && ok.ends_with("\r\n\r\nabcd"));
+		assert!(exchange(config.clone(), b"POST / HTTP/1.0\r\nContent-Length: 5\r\n\r\nabcde").starts_with("HTTP/1.1 413 "));
+		assert!(exchange(config.clone(), b"POST / HTTP/1.0\r\nContent-Length: 4\r\n\r\nab").starts_with("HTTP/1.1 408 "));
+		assert!(exchange(config.clone(), b"GET / HTTP/1.1\r\nHost:").starts_with("HTTP/1.1 408 "));
+		assert_eq!("", exchange(config.clone(), b""));
+		config.set_max_header_size(16);
+		assert!(exchange(config, b"GET /aaaaaaaaaaaaaaaaaaaa HTTP/1.0\r\n\r\n").starts_with("HTTP/1.1 431 "));
+	}
+	#[test]
+	fn test_response_head() {
+		let mut response = Response::from_str("a");
+		assert!(response.push_header("Set-Cookie", "a=1"));
+		assert!(! response.push_header("Set-Cookie", "b=2\r\nX-Injected: 1"));
+		assert!(! response.set_header("Set-Cookie", "c\n"));
+		assert!(! response.set_header("X\r\nY", "1"));
+		let head = response_head(None, &response);
+		assert!(head.contains("\r\nSet-Cookie: a=1\r\n"));
+		assert!(! head.contains("Injected") && ! head.contains("c\n"));
+		response.set_status(418);
+		assert!(response_head(None, &response).starts_with("HTTP/1.1 418 \r\n"));
+		response.set_status(1000);
+		assert!(response_head(None, &response).starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+	}
+	#[test]
+	fn test_date() {
+		let t = UNIX_EPOCH + Duration::from_secs(784111777);
+		assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_date(t));
+		assert_eq!(Some(t), parse_date(b"Sun, 06 Nov 1994 08:49:37 GMT"));
+		assert_eq!(Some(t), parse_date(b"Sunday, 06-Nov-94 08:49:37 GMT"));
+		assert_eq!(Some(t), parse_date(b"Sun Nov  6 08:49:37 1994"));
+		assert_eq!("Thu, 29 Feb 2024 23:59:59 GMT", format_date(parse_date(b"Thu, 29 Feb 2024 23:59:59 GMT").unwrap()));
+		assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", format_date(UNIX_EPOCH));
+		assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(2524608000)), parse_date(b"Saturday, 01-Jan-50 00:00:00 GMT"));
+		for bad in &["", "Sun, 06 Nov 1994 08:49:37", "Sun, 06 Foo 1994 08:49:37 GMT", "Sun, 06 Nov 1994 24:00:00 GMT", "Sun, 06 Nov 1994 8:49:37 GMT", "Wed, 31 Dec 1969 23:59:59 GMT"] {
+			assert_eq!(None, parse_date(bad.as_bytes()));
+		}
+	}
+	#[test]
+	fn test_expect_continue() {
+		let config = ServerConfig::new();
+		assert!(exchange(config.clone(), b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\n").starts_with("HTTP/1.1 401 "));
+		assert!(exchange(config.clone(), b"GET / HTTP/1.1\r\nExpect: 200-ok\r\n\r\n").starts_with("HTTP/1.1 417 "));
+		let (mut stream, server) = connect(config);
+		stream.write_all(b"POST / HTTP/1.1\r\nAuthorization: x\r\nExpect: 100-Continue\r\nContent-Length: 4\r\n\r\n").unwrap();
+		let mut interim = [0; 25];
+		stream.read_exact(&mut interim).unwrap();
+		assert_eq!(&b"HTTP/1.1 100 Continue\r\n\r\n"[..], &interim[..]);
+		stream.write_all(b"abcd").unwrap();
+		let mut output = String::new();
+		let _ = stream.read_to_string(&mut output);
+		assert!(output.starts_with("HTTP/1.1 200 OK\r\n") && output.ends_with("\r\n\r\nabcd"));
+		server.join().unwrap();
+	}
+	#[test]
+	fn test_panic_closes_connection() {
+		let metrics = Arc::new(Metrics::new());
+		let mut config = ServerConfig::new();
+		config.set_metrics(metrics.clone());
+		let (mut stream, server) = connect(config);
+		stream.write_all(b"GET /panic HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+		assert!(server.join().is_err());
+		let text = metrics.render();
+		assert!(text.contains("\nakasabi_connections_accepted_total 1\n"));
+		assert!(text.contains("\nakasabi_connections_active 0\n"));
+	}
+}
@@ -0,0 +1,152 @@
+use http::trim;
+
+// One part of a `multipart/form-data` body. Parts sent for a file input
+// have a file name, which is empty when no file was chosen.
+#[derive(Debug,PartialEq)]
+pub struct Part<'a> {
+	pub name: String,
+	pub filename: Option<String>,
+	pub content_type: Option<&'a [u8]>,
+	pub data: &'a [u8],
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Splits a value such as `form-data; name="a;b"` at the semicolons outside
+// quotes.
+fn split_params(value: &[u8]) -> Vec<&[u8]> {
+	let mut pieces = Vec::new();
+	let mut quoted = false;
+	let mut start = 0;
+	for (i, &x) in value.iter().enumerate() {
+		if x == b'"' {
+			quoted = ! quoted;
+		} else if x == b';' && ! quoted {
+			pieces.push(trim(&value[start .. i]));
+			start = i + 1;
+		}
+	}
+	pieces.push(trim(&value[start ..]));
+	pieces
+}
+
+// The value of a parameter after the first piece, without its quotes.
+// Backslashes are left alone, as some browsers send Windows paths as they
+// are.
+fn param<'a>(pieces: &[&'a [u8]], name: &[u8]) -> Option<&'a [u8]> {
+	pieces[1 ..].iter().find(|p| p.len() > name.len() && p[.. name.len()].eq_ignore_ascii_case(name) && p[name.len()] == b'=').map(|p| {
+		let value = trim(&p[name.len() + 1 ..]);
+		if value.len() >= 2 && value[0] == b'"' && value[value.len() - 1] == b'"' {
+			&value[1 .. value.len() - 1]
+		} else {
+			value
+		}
+	})
+}
+
+// Browsers send `"`, CR and LF in names and file names as `%22`, `%0D` and
+// `%0A`, and leave any other `%` alone.
+fn decode_name(name: &[u8]) -> String {
+	String::from_utf8_lossy(name).replace("%22", "\"").replace("%0D", "\r").replace("%0A", "\n")
+}
+
+// The boundary of a `multipart/form-data` Content-Type, or None for any
+// other type.
+pub fn boundary(content_type: &[u8]) -> Option<&[u8]> {
+	let pieces = split_params(content_type);
+	if ! pieces[0].eq_ignore_ascii_case(b"multipart/form-data") {
+		return None
+	}
+	param(&pieces, b"boundary").filter(|b| ! b.is_empty() && b.len() <= 70)
+}
+
+// Splits a body into its parts, or returns None if it is malformed. Text
+// before the first boundary and after the last is ignored, as are parts
+// without a name.
+pub fn parse<'a>(boundary: &[u8], body: &'a [u8]) -> Option<Vec<Part<'a>>> {
+	let mut delimiter = b"\r\n--".to_vec();
+	delimiter.extend_from_slice(boundary);
+	// The first boundary may start the body, with no line break before it.
+	let mut rest = if body.starts_with(&delimiter[2 ..]) {
+		&body[delimiter.len() - 2 ..]
+	} else {
+		&body[find(body, &delimiter)? + delimiter.len() ..]
+	};
+	let mut parts = Vec::new();
+	loop {
+		if rest.starts_with(b"--") {
+			return Some(parts)
+		}
+		let padding = rest.iter().take_while(|&&x| x == b' ' || x == b'\t').count();
+		if ! rest[padding ..].starts_with(b"\r\n") {
+			return None
+		}
+		rest = &rest[padding + 2 ..];
+		let (head, start) = if rest.starts_with(b"\r\n") {
+			(&rest[.. 0], 2)
+		} else {
+			let end = find(rest, b"\r\n\r\n")?;
+			(&rest[.. end], end + 4)
+		};
+		rest = &rest[start ..];
+		let end = find(rest, &delimiter)?;
+		let data = &rest[.. end];
+		rest = &rest[end + delimiter.len() ..];
+		let mut name = None;
+		let mut filename = None;
+		let mut content_type = None;
+		for line in head.split(|&x| x == b'\n') {
+			let line = if line.ends_with(b"\r") { &line[.. line.len() - 1] } else { line };
+			let pos = match line.iter().position(|&x| x == b':') {
+				Some(pos) => pos,
+				None => continue,
+			};
+			let value = trim(&line[pos + 1 ..]);
+			if line[.. pos].eq_ignore_ascii_case(b"Content-Disposition") {
+				let pieces = split_params(value);
+				if pieces[0].eq_ignore_ascii_case(b"form-data") {
+					name = param(&pieces, b"name").map(decode_name);
+					filename = param(&pieces, b"filename").map(decode_name);
+				}
+			} else if line[.. pos].eq_ignore_ascii_case(b"Content-Type") {
+				content_type = Some(value);
+			}
+		}
+		if let Some(name) = name {
+			parts.push(Part {
+				name: name,
+				filename: filename,
+				content_type: content_type,
+				data: data,
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Part;
+	use super::boundary;
+	use super::parse;
+	#[test]
+	fn test_boundary() {
+		assert_eq!(Some(&b"xyz"[..]), boundary(b"multipart/form-data; boundary=xyz"));
+		assert_eq!(Some(&b"a;b c"[..]), boundary(b"Multipart/Form-Data; charset=utf-8; BOUNDARY=\"a;b c\""));
+		assert_eq!(None, boundary(b"application/x-www-form-urlencoded"));
+		assert_eq!(None, boundary(b"multipart/form-data"));
+		assert_eq!(None, boundary(b"multipart/form-data; boundary=\"\""));
+	}
+	#[test]
+	fn test_parse() {
+		let body = b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n--xyz\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a%22;b.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n\r\n--xy\x00\r\n--xyz\r\n\r\nno name\r\n--xyz--\r\nepilogue";
+		assert_eq!(Some(vec![
+			Part { name: String::from("title"), filename: None, content_type: None, data: b"Hello" },
+			Part { name: String::from("photo"), filename: Some(String::from("a\";b.jpg")), content_type: Some(b"image/jpeg"), data: b"\r\n--xy\x00" },
+		]), parse(b"xyz", body));
+		assert_eq!(Some(vec![]), parse(b"xyz", b"--xyz--"));
+		assert_eq!(None, parse(b"xyz", b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nunterminated"));
+		assert_eq!(None, parse(b"xyz", b"no boundary"));
+	}
+}
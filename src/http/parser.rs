@@ -0,0 +1,87 @@
+use http::CR;
+use http::LF;
+use http::Header;
+
+// Finds the request line and header lines in a buffer as it fills. Only
+// the bytes added since the last call are scanned, and lines are kept as
+// offsets so the header can borrow them from the buffer without copying.
+#[derive(Clone)]
+pub struct HeadParser {
+	scanned: usize,
+	start: usize,
+	lines: Vec<(usize, usize)>,
+}
+
+impl Default for HeadParser {
+	fn default() -> HeadParser {
+		HeadParser::new()
+	}
+}
+
+impl HeadParser {
+
+	pub fn new() -> HeadParser {
+		HeadParser {
+			scanned: 0,
+			start: 0,
+			lines: Vec::new(),
+		}
+	}
+
+	pub fn reset(&mut self) {
+		self.scanned = 0;
+		self.start = 0;
+		self.lines.clear();
+	}
+
+	// The number of complete lines found so far.
+	pub fn lines(&self) -> usize {
+		self.lines.len()
+	}
+
+	// Returns the length of the header, including the blank line ending it,
+	// once it is complete. Empty lines before the request line are skipped.
+	pub fn advance(&mut self, buf: &[u8]) -> Option<usize> {
+		for (i, &x) in buf.iter().enumerate().skip(self.scanned) {
+			if x != LF {
+				continue;
+			}
+			let end = if i > self.start && buf[i - 1] == CR { i - 1 } else { i };
+			if end == self.start && ! self.lines.is_empty() {
+				self.scanned = i + 1;
+				return Some(i + 1);
+			}
+			if end > self.start {
+				self.lines.push((self.start, end));
+			}
+			self.start = i + 1;
+		}
+		self.scanned = buf.len();
+		None
+	}
+
+	pub fn header<'a>(&self, buf: &'a [u8]) -> Header<'a> {
+		Header { lines: self.lines.iter().map(|&(start, end)| &buf[start .. end]).collect() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::HeadParser;
+	#[test]
+	fn test_advance() {
+		let data = b"\r\nGET / HTTP/1.1\r\nHost: a\nX: b\r\n\r\nbody";
+		for split in 0 .. 34 {
+			let mut parser = HeadParser::new();
+			assert_eq!(None, parser.advance(&data[.. split]));
+			assert_eq!(Some(34), parser.advance(data));
+			let lines: &[&[u8]] = &[b"GET / HTTP/1.1", b"Host: a", b"X: b"];
+			assert_eq!(lines, parser.header(data).lines.as_slice());
+		}
+		let mut parser = HeadParser::new();
+		assert_eq!(None, parser.advance(b"GET / HTTP/1.0\r\n\r"));
+		parser.reset();
+		assert_eq!(Some(5), parser.advance(b"GET\n\nGET\n\n"));
+		assert_eq!(1, parser.lines());
+	}
+}
@@ -0,0 +1,707 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind;
+use std::io::prelude::*;
+use std::mem;
+use std::net;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use mio::Events;
+use mio::Interest;
+use mio::Poll;
+use mio::Token;
+use mio::Waker;
+use mio::net::TcpListener;
+use mio::net::TcpStream;
+
+use Handler;
+use Request;
+
+use http::CONTINUE;
+use http::Connection;
+use http::Expect;
+use http::Header;
+use http::Method;
+use http::PostData;
+use http::Protocol;
+use http::RequestImpl;
+use http::ServerConfig;
+use http::error_response;
+use http::parser::HeadParser;
+use http::response_head;
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+
+// How long to wait before accepting again after an error such as running
+// out of file descriptors.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+// A complete header, owned so that it can be handed to a worker.
+#[derive(Clone)]
+pub struct Head {
+	bytes: Vec<u8>,
+	parser: HeadParser,
+}
+
+impl Head {
+	pub fn header(&self) -> Header<'_> {
+		self.parser.header(&self.bytes)
+	}
+}
+
+pub enum Parsed {
+	Incomplete,
+	Request(Head, Option<Vec<u8>>),
+	// The client waits for `100 Continue` before sending the body.
+	Continue(Head),
+	// A status to reject the request with, and the header if it was read.
+	Error(u32, Option<Head>),
+}
+
+enum ParseState {
+	Head,
+	// Waiting for a body of the given length.
+	Body(Head, usize),
+}
+
+// Parses requests from bytes as they arrive. Each call to `parse` picks
+// up where the last one stopped, and complete requests are removed from
+// the front of the input.
+pub struct Parser {
+	head: HeadParser,
+	state: ParseState,
+}
+
+impl Default for Parser {
+	fn default() -> Parser {
+		Parser::new()
+	}
+}
+
+impl Parser {
+
+	pub fn new() -> Parser {
+		Parser {
+			head: HeadParser::new(),
+			state: ParseState::Head,
+		}
+	}
+
+	pub fn in_body(&self) -> bool {
+		match self.state {
+			ParseState::Body(..) => true,
+			ParseState::Head => false,
+		}
+	}
+
+	pub fn parse(&mut self, input: &mut Vec<u8>, config: &ServerConfig) -> Parsed {
+		if let ParseState::Head = self.state {
+			let end = self.head.advance(input);
+			if self.head.lines() > config.max_header_count + 1 || end.unwrap_or(input.len()) > config.max_header_size {
+				return Parsed::Error(431, None);
+			}
+			let end = match end {
+				Some(end) => end,
+				None => return Parsed::Incomplete,
+			};
+			let head = Head {
+				bytes: input.drain(.. end).collect(),
+				parser: mem::take(&mut self.head),
+			};
+			let (method, protocol, content_length, bad_length, expect) = {
+				let header = head.header();
+				(header.method(), header.protocol(), header.content_length(), header.bad_content_length(), header.expect())
+			};
+			if method.is_none() || protocol.is_none() {
+				return Parsed::Error(501, Some(head));
+			}
+			if bad_length {
+				return Parsed::Error(400, Some(head));
+			}
+			if expect == Some(Expect::Unknown) {
+				return Parsed::Error(417, Some(head));
+			}
+			if let Some(Method::POST) = method {
+				match content_length {
+					Some(length) if length > config.max_body_size => return Parsed::Error(413, Some(head)),
+					Some(length) if expect == Some(Expect::Continue) && input.len() < length => {
+						self.state = ParseState::Body(head.clone(), length);
+						return Parsed::Continue(head);
+					},
+					Some(length) => self.state = ParseState::Body(head, length),
+					None => return Parsed::Error(501, Some(head)),
+				}
+			} else {
+				return Parsed::Request(head, None);
+			}
+		}
+		match self.state {
+			ParseState::Body(_, length) if input.len() >= length => {},
+			_ => return Parsed::Incomplete,
+		}
+		match mem::replace(&mut self.state, ParseState::Head) {
+			ParseState::Body(head, length) => Parsed::Request(head, Some(input.drain(.. length).collect())),
+			ParseState::Head => Parsed::Incomplete,
+		}
+	}
+}
+
+struct Job {
+	token: usize,
+	peer_addr: Option<SocketAddr>,
+	head: Head,
+	body: Option<Vec<u8>>,
+	expect_continue: bool,
+	start: Instant,
+}
+
+struct Done {
+	token: usize,
+	output: Vec<u8>,
+	close: bool,
+}
+
+impl Job {
+	fn received(&self) -> usize {
+		self.head.bytes.len() + self.body.as_ref().map_or(0, |b| b.len())
+	}
+}
+
+fn work<T: Handler>(handler: &T, config: &ServerConfig, job: &Job) -> Done {
+	let header = job.head.header();
+	let post_data = match job.body {
+		Some(ref body) => PostData::Buf(body.as_slice()),
+		None => PostData::None,
+	};
+	let request = RequestImpl {
+		peer_addr: job.peer_addr,
+		header: &header,
+		post_data: &post_data,
+	};
+	let response = if job.expect_continue {
+		match handler.expect_continue(&request as &Request) {
+			Some(response) => response,
+			None => {
+				return Done {
+					token: job.token,
+					output: CONTINUE.to_vec(),
+					close: false,
+				};
+			},
+		}
+	} else {
+		let mut response = handler.handle(&request as &Request);
+		// Idle connections are cheap here, so they are kept open whenever
+		// the client allows it, unless the handler chose to close.
+		if response.connection.is_none() {
+			response.connection = Some(match (request.protocol(), request.connection()) {
+				(_, Some(Connection::Close)) => Connection::Close,
+				(_, Some(Connection::KeepAlive)) | (Some(Protocol::Http11), None) => Connection::KeepAlive,
+				_ => Connection::Close,
+			});
+		}
+		response
+	};
+	let mut output = response_head(request.protocol(), &response).into_bytes();
+	let content_length = response.content.as_ref().map_or(0, |c| c.len());
+	if let Some(ref content) = response.content {
+		output.extend_from_slice(content);
+	}
+	config.log(job.peer_addr, Some(&header), response.status, content_length, job.start);
+	config.count(Some(&header), response.status, job.received(), output.len(), job.start);
+	Done {
+		token: job.token,
+		output: output,
+		close: match response.connection() {
+			Connection::Close => true,
+			Connection::KeepAlive => false,
+		},
+	}
+}
+
+// Answers a request whose handler panicked, logging and counting the 500
+// as any other response.
+fn failed(config: &ServerConfig, job: &Job) -> Done {
+	let header = job.head.header();
+	let output = error_response(500).into_bytes();
+	config.log(job.peer_addr, Some(&header), 500, 0, job.start);
+	config.count(Some(&header), 500, job.received(), output.len(), job.start);
+	Done {
+		token: job.token,
+		output: output,
+		close: true,
+	}
+}
+
+struct Client {
+	stream: TcpStream,
+	peer_addr: Option<SocketAddr>,
+	input: Vec<u8>,
+	parser: Parser,
+	output: Vec<u8>,
+	written: usize,
+	handling: bool,
+	close: bool,
+	closed: bool,
+	deadline: Option<Instant>,
+	// The deadline last pushed onto the event loop's heap.
+	scheduled: Option<Instant>,
+	start: Instant,
+}
+
+impl Client {
+
+	fn new(stream: TcpStream, peer_addr: SocketAddr, config: &ServerConfig) -> Client {
+		Client {
+			stream: stream,
+			peer_addr: Some(peer_addr),
+			input: Vec::new(),
+			parser: Parser::new(),
+			output: Vec::new(),
+			written: 0,
+			handling: false,
+			close: false,
+			closed: false,
+			deadline: config.header_timeout.map(|t| Instant::now() + t),
+			scheduled: None,
+			start: Instant::now(),
+		}
+	}
+
+	fn is_idle(&self) -> bool {
+		! self.handling && self.output.is_empty() && self.input.is_empty() && ! self.parser.in_body()
+	}
+
+	fn respond(&mut self, output: Vec<u8>, close: bool, config: &ServerConfig) {
+		self.handling = false;
+		self.output = output;
+		self.written = 0;
+		self.close = close;
+		self.deadline = config.write_timeout.map(|t| Instant::now() + t);
+	}
+
+	fn reject(&mut self, status: u32, header: Option<&Header>, config: &ServerConfig) {
+		if status == 431 || status == 501 {
+			config.parse_error();
+		}
+		let output = error_response(status).into_bytes();
+		config.log(self.peer_addr, header, status, 0, self.start);
+		config.count(header, status, header.map_or(self.input.len(), |h| h.size()), output.len(), self.start);
+		self.respond(output, true, config);
+	}
+
+	fn dispatch(&mut self, token: usize, head: Head, body: Option<Vec<u8>>, expect_continue: bool, jobs: &Sender<Job>) {
+		self.handling = true;
+		self.deadline = None;
+		let job = Job {
+			token: token,
+			peer_addr: self.peer_addr,
+			head: head,
+			body: body,
+			expect_continue: expect_continue,
+			start: self.start,
+		};
+		if jobs.send(job).is_err() {
+			self.closed = true;
+		}
+	}
+
+	// Writes pending output, reads what has arrived and parses it, for as
+	// long as progress can be made without blocking.
+	fn drive(&mut self, token: usize, config: &ServerConfig, jobs: &Sender<Job>) {
+		let limit = config.max_header_size + config.max_body_size;
+		let mut chunk = [0; 4096];
+		while ! self.closed {
+			if ! self.output.is_empty() {
+				match self.stream.write(&self.output[self.written ..]) {
+					Ok(size) => self.written += size,
+					Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+					Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+					Err(_) => {
+						self.closed = true;
+						return;
+					},
+				}
+				if self.written < self.output.len() {
+					continue;
+				}
+				self.output.clear();
+				if self.close {
+					self.closed = true;
+					return;
+				}
+				let timeout = if self.parser.in_body() { config.body_timeout } else { config.header_timeout };
+				self.deadline = timeout.map(|t| Instant::now() + t);
+			}
+			if self.handling {
+				return;
+			}
+			let fresh = self.input.is_empty() && ! self.parser.in_body();
+			let mut blocked = false;
+			while self.input.len() < limit {
+				match self.stream.read(&mut chunk) {
+					Ok(0) => {
+						self.closed = true;
+						return;
+					},
+					Ok(size) => self.input.extend_from_slice(&chunk[.. size]),
+					Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+						blocked = true;
+						break;
+					},
+					Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+					Err(_) => {
+						self.closed = true;
+						return;
+					},
+				}
+			}
+			if self.is_idle() {
+				return;
+			}
+			if fresh {
+				self.start = Instant::now();
+			}
+			let in_body = self.parser.in_body();
+			match self.parser.parse(&mut self.input, config) {
+				Parsed::Incomplete => {
+					if ! in_body && self.parser.in_body() {
+						self.deadline = config.body_timeout.map(|t| Instant::now() + t);
+					}
+					if blocked {
+						return;
+					}
+				},
+				Parsed::Request(head, body) => {
+					self.dispatch(token, head, body, false, jobs);
+					self.start = Instant::now();
+					return;
+				},
+				Parsed::Continue(head) => {
+					self.dispatch(token, head, None, true, jobs);
+					return;
+				},
+				Parsed::Error(status, head) => {
+					let header = head.as_ref().map(|h| h.header());
+					self.reject(status, header.as_ref(), config);
+				},
+			}
+		}
+	}
+
+	// Called once the deadline has passed.
+	fn expire(&mut self, config: &ServerConfig) {
+		if ! self.output.is_empty() || self.is_idle() {
+			self.closed = true;
+		} else if ! self.handling {
+			self.input.clear();
+			self.reject(408, None, config);
+		}
+	}
+}
+
+// An event loop server. One thread waits on all connections with epoll
+// (via mio) and a pool of worker threads runs the handler.
+pub struct Server<T> {
+	handler: Arc<T>,
+	config: ServerConfig,
+	workers: usize,
+}
+
+impl<T: Handler + Send + Sync + 'static> Server<T> {
+
+	pub fn new(h: T) -> Server<T> {
+		Server::with_config(h, ServerConfig::new())
+	}
+
+	pub fn with_config(h: T, config: ServerConfig) -> Server<T> {
+		Server {
+			handler: Arc::new(h),
+			config: config,
+			workers: 4,
+		}
+	}
+
+	pub fn set_workers(&mut self, n: usize) {
+		self.workers = n;
+	}
+
+	fn spawn_workers(&self, waker: &Arc<Waker>, done: &Sender<Done>) -> Sender<Job> {
+		let (sender, receiver) = mpsc::channel::<Job>();
+		let receiver = Arc::new(Mutex::new(receiver));
+		for _ in 0 .. self.workers {
+			let handler = self.handler.clone();
+			let config = self.config.clone();
+			let receiver = receiver.clone();
+			let done = done.clone();
+			let waker = waker.clone();
+			thread::spawn(move || loop {
+				let job = match receiver.lock() {
+					Ok(receiver) => receiver.recv(),
+					Err(_) => return,
+				};
+				let job = match job {
+					Ok(job) => job,
+					Err(_) => return,
+				};
+				let result = panic::catch_unwind(AssertUnwindSafe(|| work(&*handler, &config, &job)));
+				let result = result.unwrap_or_else(|_| failed(&config, &job));
+				if done.send(result).is_err() {
+					return;
+				}
+				let _ = waker.wake();
+			});
+		}
+		sender
+	}
+
+	pub fn run(&self, listener: net::TcpListener) -> io::Result<()> {
+		listener.set_nonblocking(true)?;
+		let mut listener = TcpListener::from_std(listener);
+		let mut poll = Poll::new()?;
+		poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+		let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+		let (done_sender, done_receiver) = mpsc::channel::<Done>();
+		let jobs = self.spawn_workers(&waker, &done_sender);
+
+		let config = &self.config;
+		let mut clients: HashMap<usize, Client> = HashMap::new();
+		// Deadlines with their clients, soonest first. Entries that no longer
+		// match their client's deadline are skipped as they come up.
+		let mut deadlines: BinaryHeap<Reverse<(Instant, usize)>> = BinaryHeap::new();
+		let mut retry_accept: Option<Instant> = None;
+		let mut next_token = WAKER.0 + 1;
+		let mut events = Events::with_capacity(1024);
+		loop {
+			while let Some(&Reverse((deadline, token))) = deadlines.peek() {
+				if clients.get(&token).is_some_and(|c| c.deadline == Some(deadline)) {
+					break;
+				}
+				deadlines.pop();
+			}
+			let now = Instant::now();
+			let timeout = deadlines.peek().map(|d| (d.0).0).into_iter().chain(retry_accept).min().map(|d| d.saturating_duration_since(now));
+			if let Err(e) = poll.poll(&mut events, timeout) {
+				if e.kind() == ErrorKind::Interrupted {
+					continue;
+				}
+				return Err(e);
+			}
+			let mut active: Vec<usize> = Vec::new();
+			let mut accept = false;
+			for event in events.iter() {
+				match event.token() {
+					LISTENER => accept = true,
+					WAKER => {
+						while let Ok(done) = done_receiver.try_recv() {
+							if let Some(client) = clients.get_mut(&done.token) {
+								client.respond(done.output, done.close, config);
+								active.push(done.token);
+							}
+						}
+					},
+					Token(token) => active.push(token),
+				}
+			}
+			let now = Instant::now();
+			if retry_accept.is_some_and(|t| t <= now) {
+				retry_accept = None;
+				accept = true;
+			}
+			while accept {
+				match listener.accept() {
+					Ok((mut stream, peer_addr)) => {
+						let token = next_token;
+						next_token += 1;
+						if poll.registry().register(&mut stream, Token(token), Interest::READABLE | Interest::WRITABLE).is_err() {
+							continue;
+						}
+						if let Some(ref metrics) = config.metrics {
+							metrics.open_connection();
+						}
+						clients.insert(token, Client::new(stream, peer_addr, config));
+						active.push(token);
+					},
+					Err(ref e) if e.kind() == ErrorKind::WouldBlock => accept = false,
+					Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+					// No new event comes for connections still queued, so
+					// accepting is retried after a pause.
+					Err(_) => {
+						retry_accept = Some(now + ACCEPT_RETRY);
+						accept = false;
+					},
+				}
+			}
+			while let Some(&Reverse((deadline, token))) = deadlines.peek() {
+				if deadline > now {
+					break;
+				}
+				deadlines.pop();
+				if let Some(client) = clients.get_mut(&token) {
+					if client.deadline == Some(deadline) {
+						client.deadline = None;
+						client.expire(config);
+						active.push(token);
+					}
+				}
+			}
+			let mut closed: Vec<usize> = Vec::new();
+			for token in active {
+				if let Some(client) = clients.get_mut(&token) {
+					client.drive(token, config, &jobs);
+					match client.deadline {
+						_ if client.closed => closed.push(token),
+						Some(deadline) if client.scheduled != Some(deadline) => {
+							deadlines.push(Reverse((deadline, token)));
+							client.scheduled = Some(deadline);
+						},
+						_ => {},
+					}
+				}
+			}
+			for token in closed {
+				if let Some(mut client) = clients.remove(&token) {
+					let _ = poll.registry().deregister(&mut client.stream);
+					let _ = client.stream.shutdown(Shutdown::Both);
+					if let Some(ref metrics) = config.metrics {
+						metrics.close_connection();
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::io::prelude::*;
+	use std::net::TcpListener;
+	use std::sync::Arc;
+	use std::net::TcpStream;
+	use std::thread;
+	use std::time::Duration;
+	use super::Parsed;
+	use super::Parser;
+	use super::Server;
+	use http::Connection;
+	use http::ServerConfig;
+	use log::AccessLog;
+	use log::LogFormat;
+	use metrics::Metrics;
+	use Handler;
+	use Request;
+	use Response;
+	#[test]
+	fn test_parse_incremental() {
+		let config = ServerConfig::new();
+		let mut parser = Parser::new();
+		let mut input: Vec<u8> = Vec::new();
+		let data = b"\r\nPOST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\n\n";
+		let mut requests = Vec::new();
+		for &x in data.iter() {
+			input.push(x);
+			loop {
+				match parser.parse(&mut input, &config) {
+					Parsed::Request(head, body) => requests.push((head.header().lines[0].to_vec(), body)),
+					Parsed::Incomplete => break,
+					Parsed::Continue(_) => panic!("continue"),
+					Parsed::Error(status, _) => panic!("{}", status),
+				}
+			}
+		}
+		assert_eq!(vec![
+			(b"POST /a HTTP/1.1".to_vec(), Some(b"abc".to_vec())),
+			(b"GET /b HTTP/1.1".to_vec(), None),
+		], requests);
+		assert!(input.is_empty());
+		let mut input = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n".to_vec();
+		match parser.parse(&mut input, &config) {
+			Parsed::Continue(head) => assert_eq!(3, head.header().lines.len()),
+			_ => panic!("expected continue"),
+		}
+		input.extend_from_slice(b"ok");
+		match parser.parse(&mut input, &config) {
+			Parsed::Request(_, body) => assert_eq!(Some(b"ok".to_vec()), body),
+			_ => panic!("expected request"),
+		}
+		let mut input = b"GET / HTTP/1.1\r\nExpect: nothing\r\n\r\n".to_vec();
+		match parser.parse(&mut input, &config) {
+			Parsed::Error(status, _) => assert_eq!(417, status),
+			_ => panic!("expected error"),
+		}
+		for length in &["99999999999999999999999", "1a2", "", "-1"] {
+			let mut input = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", length).into_bytes();
+			match Parser::new().parse(&mut input, &config) {
+				Parsed::Error(status, _) => assert_eq!(400, status),
+				_ => panic!("expected error"),
+			}
+		}
+	}
+	struct Path;
+	impl Handler for Path {
+		fn handle(&self, req: &Request) -> Response {
+			let mut response = Response::from_string(String::from_utf8(req.path().unwrap().to_vec()).unwrap());
+			response.set_header("Content-Type", "text/plain");
+			if req.path() == Some(b"/close") {
+				response.set_connection(Connection::Close);
+			} else if req.path() == Some(b"/panic") {
+				panic!("handler failed");
+			}
+			response
+		}
+	}
+	#[test]
+	fn test_server() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let dir = ::std::env::temp_dir().join(format!("akasabi-server-{}", ::std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("access.log");
+		let metrics = Arc::new(Metrics::new());
+		let mut config = ServerConfig::new();
+		config.set_access_log(AccessLog::file(&path, LogFormat::Common).unwrap());
+		config.set_metrics(metrics.clone());
+		config.set_max_header_count(2);
+		config.set_header_timeout(Some(Duration::from_millis(100)));
+		thread::spawn(move || Server::with_config(Path, config).run(listener));
+		let exchange = |input: &[u8]| {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream.write_all(input).unwrap();
+			let mut output = String::new();
+			let _ = stream.read_to_string(&mut output);
+			output
+		};
+		let output = exchange(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+		assert_eq!(2, output.matches("HTTP/1.1 200 OK\r\n").count());
+		assert!(output.contains("\r\n\r\n/a") && output.ends_with("\r\n\r\n/b"));
+		// A handler closing the connection drops the pipelined request.
+		let output = exchange(b"GET /close HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n");
+		assert_eq!(1, output.matches("HTTP/1.1 200 OK\r\n").count());
+		assert!(output.contains("Connection: close\r\n") && output.ends_with("\r\n\r\n/close"));
+		// A panicking handler is answered, logged and counted as a 500.
+		assert!(exchange(b"GET /panic HTTP/1.1\r\nHost: x\r\n\r\n").starts_with("HTTP/1.1 500 "));
+		assert!(metrics.render().contains("akasabi_requests_total{method=\"GET\",status=\"500\"} 1\n"));
+		let mut log = String::new();
+		fs::File::open(&path).unwrap().read_to_string(&mut log).unwrap();
+		assert!(log.contains("\"GET /panic HTTP/1.1\" 500 -\n"));
+		let _ = fs::remove_dir_all(&dir);
+		assert!(exchange(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").starts_with("HTTP/1.1 431 "));
+		assert!(exchange(b"GET / HTTP/1.1\r\nHost").starts_with("HTTP/1.1 408 "));
+		assert_eq!("", exchange(b""));
+	}
+}
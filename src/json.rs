@@ -0,0 +1,263 @@
+use std::char;
+use std::fmt;
+use std::str::from_utf8;
+
+// Nesting deeper than this is rejected rather than risking the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Value>),
+	Object(Vec<(String, Value)>),
+}
+
+impl Value {
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match *self {
+			Value::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+			_ => None,
+		}
+	}
+	pub fn as_str(&self) -> Option<&str> {
+		match *self {
+			Value::String(ref s) => Some(s.as_str()),
+			_ => None,
+		}
+	}
+	pub fn as_f64(&self) -> Option<f64> {
+		match *self {
+			Value::Number(n) => Some(n),
+			_ => None,
+		}
+	}
+	pub fn as_array(&self) -> Option<&[Value]> {
+		match *self {
+			Value::Array(ref items) => Some(items.as_slice()),
+			_ => None,
+		}
+	}
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+	f.write_str("\"")?;
+	for c in s.chars() {
+		match c {
+			'"' => f.write_str("\\\"")?,
+			'\\' => f.write_str("\\\\")?,
+			'\n' => f.write_str("\\n")?,
+			'\r' => f.write_str("\\r")?,
+			'\t' => f.write_str("\\t")?,
+			c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+			c => write!(f, "{}", c)?,
+		}
+	}
+	f.write_str("\"")
+}
+
+// Writes the value back as compact JSON.
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Value::Null => f.write_str("null"),
+			Value::Bool(b) => write!(f, "{}", b),
+			Value::Number(n) => write!(f, "{}", n),
+			Value::String(ref s) => write_string(f, s),
+			Value::Array(ref items) => {
+				f.write_str("[")?;
+				for (i, item) in items.iter().enumerate() {
+					if i > 0 {
+						f.write_str(",")?;
+					}
+					write!(f, "{}", item)?;
+				}
+				f.write_str("]")
+			}
+			Value::Object(ref members) => {
+				f.write_str("{")?;
+				for (i, (key, value)) in members.iter().enumerate() {
+					if i > 0 {
+						f.write_str(",")?;
+					}
+					write_string(f, key)?;
+					write!(f, ":{}", value)?;
+				}
+				f.write_str("}")
+			}
+		}
+	}
+}
+
+struct Parser<'a> {
+	input: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn skip_whitespace(&mut self) {
+		while self.pos < self.input.len() && b" \t\r\n".contains(&self.input[self.pos]) {
+			self.pos += 1;
+		}
+	}
+	fn peek(&mut self) -> Option<u8> {
+		self.skip_whitespace();
+		self.input.get(self.pos).cloned()
+	}
+	fn expect(&mut self, literal: &[u8]) -> Option<()> {
+		if self.input[self.pos ..].starts_with(literal) {
+			self.pos += literal.len();
+			Some(())
+		} else {
+			None
+		}
+	}
+	fn value(&mut self, depth: usize) -> Option<Value> {
+		if depth > MAX_DEPTH {
+			return None
+		}
+		match self.peek()? {
+			b'n' => self.expect(b"null").map(|_| Value::Null),
+			b't' => self.expect(b"true").map(|_| Value::Bool(true)),
+			b'f' => self.expect(b"false").map(|_| Value::Bool(false)),
+			b'"' => self.string().map(Value::String),
+			b'[' => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				if self.peek()? == b']' {
+					self.pos += 1;
+					return Some(Value::Array(items))
+				}
+				loop {
+					items.push(self.value(depth + 1)?);
+					match self.peek()? {
+						b',' => self.pos += 1,
+						b']' => break,
+						_ => return None,
+					}
+				}
+				self.pos += 1;
+				Some(Value::Array(items))
+			}
+			b'{' => {
+				self.pos += 1;
+				let mut members = Vec::new();
+				if self.peek()? == b'}' {
+					self.pos += 1;
+					return Some(Value::Object(members))
+				}
+				loop {
+					if self.peek()? != b'"' {
+						return None
+					}
+					let key = self.string()?;
+					if self.peek()? != b':' {
+						return None
+					}
+					self.pos += 1;
+					members.push((key, self.value(depth + 1)?));
+					match self.peek()? {
+						b',' => self.pos += 1,
+						b'}' => break,
+						_ => return None,
+					}
+				}
+				self.pos += 1;
+				Some(Value::Object(members))
+			}
+			_ => self.number(),
+		}
+	}
+	fn number(&mut self) -> Option<Value> {
+		let start = self.pos;
+		while self.pos < self.input.len() && b"+-.eE0123456789".contains(&self.input[self.pos]) {
+			self.pos += 1;
+		}
+		let text = from_utf8(&self.input[start .. self.pos]).ok()?;
+		// Rust accepts forms JSON doesn't, such as `+1`, `.5` and `1.`.
+		let digits = text.trim_start_matches('-');
+		if digits.starts_with(|c: char| ! c.is_ascii_digit()) || (digits.starts_with('0') && digits[1 ..].starts_with(|c: char| c.is_ascii_digit())) || text.contains(".e") || text.contains(".E") || text.ends_with('.') {
+			return None
+		}
+		text.parse().ok().map(Value::Number)
+	}
+	fn hex4(&mut self) -> Option<u32> {
+		let hex = from_utf8(self.input.get(self.pos .. self.pos + 4)?).ok()?;
+		self.pos += 4;
+		u32::from_str_radix(hex, 16).ok()
+	}
+	fn string(&mut self) -> Option<String> {
+		self.pos += 1;
+		let mut buf = Vec::new();
+		loop {
+			let x = *self.input.get(self.pos)?;
+			self.pos += 1;
+			match x {
+				b'"' => break,
+				b'\\' => {
+					let escape = *self.input.get(self.pos)?;
+					self.pos += 1;
+					let c = match escape {
+						b'"' => '"',
+						b'\\' => '\\',
+						b'/' => '/',
+						b'b' => '\u{8}',
+						b'f' => '\u{c}',
+						b'n' => '\n',
+						b'r' => '\r',
+						b't' => '\t',
+						b'u' => {
+							let high = self.hex4()?;
+							if (0xD800 .. 0xDC00).contains(&high) {
+								self.expect(b"\\u")?;
+								let low = self.hex4()?;
+								if ! (0xDC00 .. 0xE000).contains(&low) {
+									return None
+								}
+								char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))?
+							} else {
+								char::from_u32(high)?
+							}
+						}
+						_ => return None,
+					};
+					let mut utf8 = [0; 4];
+					buf.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+				}
+				x if x < 0x20 => return None,
+				x => buf.push(x),
+			}
+		}
+		String::from_utf8(buf).ok()
+	}
+}
+
+// Parses a complete JSON document.
+pub fn parse(input: &[u8]) -> Option<Value> {
+	let mut parser = Parser { input: input, pos: 0 };
+	let value = parser.value(0)?;
+	match parser.peek() {
+		None => Some(value),
+		Some(_) => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Value;
+	use super::parse;
+	#[test]
+	fn test_parse() {
+		let value = parse(b" {\"a\": [1, -2.5e3, true, null], \"b\": {\"c\": \"x\\\"\\u00e9\\ud83d\\ude00\"}, \"\": []} ").unwrap();
+		assert_eq!(Some(&Value::Number(-2500.0)), value.get("a").and_then(|a| a.as_array()).map(|a| &a[1]));
+		assert_eq!(Some("x\"é😀"), value.get("b").and_then(|b| b.get("c")).and_then(|c| c.as_str()));
+		assert_eq!("{\"a\":[1,-2500,true,null],\"b\":{\"c\":\"x\\\"é😀\"},\"\":[]}", value.to_string());
+		for bad in &["", "{", "[1,]", "{\"a\" 1}", "01", "+1", ".5", "1.", "1.e5", "\"\\ud83d\"", "\"a\nb\"", "tru", "1 2", "{1: 2}"] {
+			assert_eq!(None, parse(bad.as_bytes()), "{}", bad);
+		}
+		assert_eq!(None, parse("[".repeat(100).as_bytes()));
+		assert_eq!(Some(Value::Number(0.5)), parse(b"0.5"));
+	}
+}
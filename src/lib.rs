@@ -1,17 +1,46 @@
+extern crate mio;
+
+#[cfg(test)]
+extern crate quickcheck;
+
 use std::str::from_utf8;
 use std::net::SocketAddr;
+use std::time::SystemTime;
 
 use http::Header;
 use http::Protocol;
 use http::Method;
 use http::Connection;
+use http::accept;
+use http::multipart;
+use http::multipart::Part;
+
+use url::Uri;
 
 pub mod http;
 pub mod url;
 pub mod html;
+pub mod vhost;
+pub mod crypto;
+pub mod csrf;
+pub mod log;
+pub mod metrics;
+pub mod conditional;
+pub mod cache;
+pub mod auth;
+pub mod json;
+pub mod jwt;
+pub mod cors;
+pub mod ratelimit;
 
 pub trait Handler {
 	fn handle(&self, &Request) -> Response;
+	// Called with the header alone when a client sends `Expect: 100-continue`
+	// and waits before sending the body. Returning a response rejects the
+	// request without reading the body.
+	fn expect_continue(&self, _: &Request) -> Option<Response> {
+		None
+	}
 }
 
 pub trait Request {
@@ -19,30 +48,133 @@ pub trait Request {
 	fn protocol(&self) -> Option<Protocol>;
 	fn method(&self) -> Option<Method>;
 	fn path(&self) -> Option<&[u8]>;
+	fn uri(&self) -> Option<Uri>;
 	fn connection(&self) -> Option<Connection>;
 	fn content_length(&self) -> Option<usize>;
+	fn host(&self) -> Option<&[u8]>;
 	fn post_data(&self) -> Option<&[u8]>;
-	fn header(&self) -> &Header;
+	fn header(&self) -> &Header<'_>;
 	fn get_params(&self) -> Params;
 	fn post_params(&self) -> Params;
+	fn attr(&self, name: &str) -> Option<&str>;
+	// Picks the media type the client prefers by `Accept`, or a 406
+	// response if it accepts none of them.
+	fn negotiate<'b>(&self, types: &[&'b str]) -> Result<&'b str, Response> {
+		accept::negotiate(self.header().get_string(b"Accept"), types, accept::match_media).ok_or_else(|| Response::from_status(406))
+	}
+	fn negotiate_language<'b>(&self, langs: &[&'b str]) -> Option<&'b str> {
+		accept::negotiate(self.header().get_string(b"Accept-Language"), langs, accept::match_language)
+	}
+	fn negotiate_charset<'b>(&self, charsets: &[&'b str]) -> Option<&'b str> {
+		accept::negotiate(self.header().get_string(b"Accept-Charset"), charsets, accept::match_charset)
+	}
+	// The parts of a `multipart/form-data` body, or None for any other body.
+	fn multipart(&self) -> Option<Vec<Part<'_>>> {
+		let boundary = multipart::boundary(self.header().get_string(b"Content-Type")?)?;
+		multipart::parse(boundary, self.post_data()?)
+	}
+	// The user authenticated by `auth::Auth`.
+	fn user(&self) -> Option<&str> {
+		self.attr(auth::USER_ATTR)
+	}
+	// A claim of the token verified by `jwt::Jwt`, as JSON unless a string.
+	fn claim(&self, name: &str) -> Option<&str> {
+		self.attr(&format!("{}{}", jwt::CLAIM_ATTR_PREFIX, name))
+	}
 }
 
+// Wraps a request with extra named attributes, so that a handler wrapping
+// another one can pass values such as tokens or user names down to it.
+pub struct ExtendedRequest<'a> {
+	inner: &'a Request,
+	attrs: Vec<(String, String)>,
+}
+
+impl<'a> ExtendedRequest<'a> {
+	pub fn new(inner: &'a Request) -> ExtendedRequest<'a> {
+		ExtendedRequest {
+			inner: inner,
+			attrs: Vec::new(),
+		}
+	}
+	pub fn push_attr(&mut self, name: &str, value: &str) {
+		self.attrs.push((String::from(name), String::from(value)));
+	}
+}
+
+impl<'a> Request for ExtendedRequest<'a> {
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		self.inner.peer_addr()
+	}
+	fn protocol(&self) -> Option<Protocol> {
+		self.inner.protocol()
+	}
+	fn method(&self) -> Option<Method> {
+		self.inner.method()
+	}
+	fn path(&self) -> Option<&[u8]> {
+		self.inner.path()
+	}
+	fn uri(&self) -> Option<Uri> {
+		self.inner.uri()
+	}
+	fn connection(&self) -> Option<Connection> {
+		self.inner.connection()
+	}
+	fn content_length(&self) -> Option<usize> {
+		self.inner.content_length()
+	}
+	fn host(&self) -> Option<&[u8]> {
+		self.inner.host()
+	}
+	fn post_data(&self) -> Option<&[u8]> {
+		self.inner.post_data()
+	}
+	fn header(&self) -> &Header<'_> {
+		self.inner.header()
+	}
+	fn get_params(&self) -> Params {
+		self.inner.get_params()
+	}
+	fn post_params(&self) -> Params {
+		self.inner.post_params()
+	}
+	fn attr(&self, name: &str) -> Option<&str> {
+		match self.attrs.iter().rev().find(|a| a.0 == name) {
+			Some(a) => Some(a.1.as_str()),
+			None => self.inner.attr(name),
+		}
+	}
+}
+
+fn has_line_break(str: &str) -> bool {
+	str.contains(&['\r', '\n'][..])
+}
+
+#[derive(Clone)]
 pub struct Response {
 	content: Option<Vec<u8>>,
-	connection: Connection,
+	connection: Option<Connection>,
 	status: u32,
+	headers: Vec<(String, String)>,
 }
 
 impl Response {
 	fn new(contents: Option<Vec<u8>>) -> Response {
 		Response {
 			content: contents,
-			connection: Connection::Close,
+			connection: None,
 			status: 200,
+			headers: Vec::new(),
 		}
 	}
+	// Unless set, the connection is closed by the blocking server and kept
+	// open by the event loop whenever the client allows it.
 	fn connection(&self) -> Connection {
-		self.connection
+		self.connection.unwrap_or(Connection::Close)
+	}
+	pub fn set_connection(&mut self, connection: Connection) {
+		self.connection = Some(connection);
 	}
 	pub fn from_str(contents: &str) -> Response {
 		Response::new(Some(contents.as_bytes().to_vec()))
@@ -50,6 +182,55 @@ impl Response {
 	pub fn from_string(contents: String) -> Response {
 		Response::new(Some(contents.as_bytes().to_vec()))
 	}
+	pub fn from_status(status: u32) -> Response {
+		let mut response = Response::new(None);
+		response.status = status;
+		if let Some(reason) = http::reason_phrase(status) {
+			response.content = Some(format!("{} {}", status, reason).into_bytes());
+		}
+		response
+	}
+	pub fn status(&self) -> u32 {
+		self.status
+	}
+	pub fn set_status(&mut self, status: u32) {
+		self.status = status;
+	}
+	pub fn get_header(&self, name: &str) -> Option<&str> {
+		self.headers.iter().find(|h| h.0.eq_ignore_ascii_case(name)).map(|h| h.1.as_str())
+	}
+	// Adds a header, keeping any others of the same name (e.g. Set-Cookie).
+	// A name or value containing CR or LF, which could end the header early
+	// and start another, is refused and false returned.
+	pub fn push_header(&mut self, name: &str, value: &str) -> bool {
+		if has_line_break(name) || has_line_break(value) {
+			return false;
+		}
+		self.headers.push((String::from(name), String::from(value)));
+		true
+	}
+	// Replaces every header of the same name, unless refused as above.
+	pub fn set_header(&mut self, name: &str, value: &str) -> bool {
+		if has_line_break(name) || has_line_break(value) {
+			return false;
+		}
+		self.headers.retain(|h| ! h.0.eq_ignore_ascii_case(name));
+		self.push_header(name, value)
+	}
+	pub fn headers(&self) -> &[(String, String)] {
+		self.headers.as_slice()
+	}
+	// Sets a strong `ETag`, which must change with any change to the content.
+	pub fn set_etag(&mut self, tag: &str) {
+		self.set_header("ETag", format!("\"{}\"", tag).as_str());
+	}
+	// Sets a weak `ETag`, for content that is only equivalent between changes.
+	pub fn set_weak_etag(&mut self, tag: &str) {
+		self.set_header("ETag", format!("W/\"{}\"", tag).as_str());
+	}
+	pub fn set_last_modified(&mut self, time: SystemTime) {
+		self.set_header("Last-Modified", http::format_date(time).as_str());
+	}
 }
 
 pub struct Param<'a> {
@@ -0,0 +1,268 @@
+extern crate time;
+
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum LogFormat {
+	Common,
+	Combined,
+	Json,
+}
+
+pub struct LogEntry<'a> {
+	pub peer_addr: Option<SocketAddr>,
+	pub time: time::Tm,
+	pub request_line: Option<&'a [u8]>,
+	pub status: u32,
+	pub bytes: usize,
+	pub referer: Option<&'a [u8]>,
+	pub user_agent: Option<&'a [u8]>,
+	pub duration: Duration,
+}
+
+// Quoted fields in the Common Log Format escape quotes, backslashes and
+// non-printable bytes the way Apache does.
+fn push_clf_quoted(buf: &mut String, value: Option<&[u8]>) {
+	buf.push('"');
+	match value {
+		Some(value) => {
+			for &x in value {
+				match x {
+					b'"' => buf.push_str("\\\""),
+					b'\\' => buf.push_str("\\\\"),
+					0x20 ..= 0x7E => buf.push(x as char),
+					_ => buf.push_str(&format!("\\x{:02X}", x)),
+				}
+			}
+		},
+		None => buf.push('-'),
+	}
+	buf.push('"');
+}
+
+fn push_json_string(buf: &mut String, value: Option<&[u8]>) {
+	let value = match value {
+		Some(value) => String::from_utf8_lossy(value),
+		None => {
+			buf.push_str("null");
+			return;
+		},
+	};
+	buf.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => buf.push_str("\\\""),
+			'\\' => buf.push_str("\\\\"),
+			'\n' => buf.push_str("\\n"),
+			'\r' => buf.push_str("\\r"),
+			'\t' => buf.push_str("\\t"),
+			_ if c.is_control() => buf.push_str(&format!("\\u{:04x}", c as u32)),
+			_ => buf.push(c),
+		}
+	}
+	buf.push('"');
+}
+
+impl<'a> LogEntry<'a> {
+
+	pub fn format(&self, format: LogFormat) -> String {
+		let mut buf = String::new();
+		let host = self.peer_addr.map(|addr| addr.ip().to_string());
+		match format {
+			LogFormat::Common | LogFormat::Combined => {
+				buf.push_str(host.as_deref().unwrap_or("-"));
+				buf.push_str(" - - [");
+				buf.push_str(&self.time.strftime("%d/%b/%Y:%H:%M:%S").map(|t| t.to_string()).unwrap_or_default());
+				// `%z` writes UTC as -0000.
+				let offset = self.time.tm_utcoff;
+				buf.push_str(&format!(" {}{:02}{:02}", if offset < 0 { '-' } else { '+' }, offset.abs() / 3600, offset.abs() / 60 % 60));
+				buf.push_str("] ");
+				push_clf_quoted(&mut buf, self.request_line);
+				buf.push_str(&format!(" {} ", self.status));
+				if self.bytes > 0 {
+					buf.push_str(&self.bytes.to_string());
+				} else {
+					buf.push('-');
+				}
+				if format == LogFormat::Combined {
+					buf.push(' ');
+					push_clf_quoted(&mut buf, self.referer);
+					buf.push(' ');
+					push_clf_quoted(&mut buf, self.user_agent);
+				}
+			},
+			LogFormat::Json => {
+				buf.push_str("{\"remote_addr\":");
+				push_json_string(&mut buf, host.as_ref().map(|h| h.as_bytes()));
+				buf.push_str(",\"time\":");
+				push_json_string(&mut buf, Some(self.time.rfc3339().to_string().as_bytes()));
+				buf.push_str(",\"request\":");
+				push_json_string(&mut buf, self.request_line);
+				buf.push_str(&format!(",\"status\":{},\"bytes\":{}", self.status, self.bytes));
+				buf.push_str(",\"referer\":");
+				push_json_string(&mut buf, self.referer);
+				buf.push_str(",\"user_agent\":");
+				push_json_string(&mut buf, self.user_agent);
+				buf.push_str(&format!(",\"duration_us\":{}}}", self.duration.as_secs() * 1_000_000 + self.duration.subsec_micros() as u64));
+			},
+		}
+		buf
+	}
+}
+
+enum Target {
+	Stdout,
+	File {
+		path: PathBuf,
+		file: File,
+		size: u64,
+	},
+}
+
+// Writes one line per request. A log file is rotated to `<path>.1`,
+// `<path>.2`, ... when it would grow beyond the maximum size.
+pub struct AccessLog {
+	format: LogFormat,
+	max_size: Option<u64>,
+	max_files: usize,
+	target: Mutex<Target>,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+	OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+	let mut name = path.as_os_str().to_owned();
+	name.push(format!(".{}", n));
+	PathBuf::from(name)
+}
+
+impl AccessLog {
+
+	pub fn stdout(format: LogFormat) -> AccessLog {
+		AccessLog {
+			format: format,
+			max_size: None,
+			max_files: 0,
+			target: Mutex::new(Target::Stdout),
+		}
+	}
+
+	pub fn file<P: AsRef<Path>>(path: P, format: LogFormat) -> io::Result<AccessLog> {
+		let path = path.as_ref().to_path_buf();
+		let file = open_append(&path)?;
+		let size = file.metadata()?.len();
+		Ok(AccessLog {
+			format: format,
+			max_size: None,
+			max_files: 5,
+			target: Mutex::new(Target::File { path: path, file: file, size: size }),
+		})
+	}
+
+	pub fn set_max_size(&mut self, size: Option<u64>) {
+		self.max_size = size;
+	}
+	// The number of rotated files kept besides the current one.
+	pub fn set_max_files(&mut self, n: usize) {
+		self.max_files = n;
+	}
+
+	fn rotate(&self, path: &Path) -> io::Result<File> {
+		if self.max_files == 0 {
+			return OpenOptions::new().create(true).write(true).truncate(true).open(path);
+		}
+		for n in (1 .. self.max_files).rev() {
+			let from = rotated_path(path, n);
+			if from.exists() {
+				fs::rename(&from, rotated_path(path, n + 1))?;
+			}
+		}
+		fs::rename(path, rotated_path(path, 1))?;
+		open_append(path)
+	}
+
+	pub fn log(&self, entry: &LogEntry) {
+		let mut line = entry.format(self.format);
+		line.push('\n');
+		let mut target = match self.target.lock() {
+			Ok(target) => target,
+			Err(poisoned) => poisoned.into_inner(),
+		};
+		match *target {
+			Target::Stdout => {
+				let stdout = io::stdout();
+				let _ = stdout.lock().write_all(line.as_bytes());
+			},
+			Target::File { ref path, ref mut file, ref mut size } => {
+				if let Some(max_size) = self.max_size {
+					if *size > 0 && *size + line.len() as u64 > max_size {
+						if let Ok(rotated) = self.rotate(path) {
+							*file = rotated;
+							*size = 0;
+						}
+					}
+				}
+				if file.write_all(line.as_bytes()).is_ok() {
+					*size += line.len() as u64;
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate time;
+	use std::fs;
+	use std::time::Duration;
+	use super::AccessLog;
+	use super::LogEntry;
+	use super::LogFormat;
+	fn entry() -> LogEntry<'static> {
+		LogEntry {
+			peer_addr: "127.0.0.1:50000".parse().ok(),
+			time: time::at_utc(time::Timespec::new(971186136, 0)),
+			request_line: Some(b"GET /a\"b HTTP/1.1"),
+			status: 200,
+			bytes: 2326,
+			referer: None,
+			user_agent: Some(b"curl/7.0"),
+			duration: Duration::from_millis(12),
+		}
+	}
+	#[test]
+	fn test_format() {
+		let entry = entry();
+		assert_eq!("127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326", entry.format(LogFormat::Common));
+		assert_eq!("127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326 \"-\" \"curl/7.0\"", entry.format(LogFormat::Combined));
+		assert_eq!("{\"remote_addr\":\"127.0.0.1\",\"time\":\"2000-10-10T13:55:36Z\",\"request\":\"GET /a\\\"b HTTP/1.1\",\"status\":200,\"bytes\":2326,\"referer\":null,\"user_agent\":\"curl/7.0\",\"duration_us\":12000}", entry.format(LogFormat::Json));
+	}
+	#[test]
+	fn test_rotate() {
+		let dir = ::std::env::temp_dir().join(format!("akasabi-log-{}", ::std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("access.log");
+		let mut log = AccessLog::file(&path, LogFormat::Common).unwrap();
+		log.set_max_size(Some(100));
+		log.set_max_files(1);
+		for _ in 0 .. 3 {
+			log.log(&entry());
+		}
+		assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+		assert_eq!(1, fs::read_to_string(dir.join("access.log.1")).unwrap().lines().count());
+		assert!(! dir.join("access.log.2").exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,13 +1,14 @@
 extern crate akasabi;
 
-use std::str;
 use std::net::TcpListener;
-
-use akasabi::http::Protocol;
-use akasabi::http::Method;
-use akasabi::http::Connection;
+use std::sync::Arc;
 
 use akasabi::http::HttpHandler;
+use akasabi::http::ServerConfig;
+use akasabi::log::AccessLog;
+use akasabi::log::LogFormat;
+use akasabi::metrics::Metrics;
+use akasabi::metrics::MetricsHandler;
 use akasabi::Handler;
 use akasabi::Request;
 use akasabi::Response;
@@ -18,41 +19,7 @@ use akasabi::html::builder::Tag;
 struct MyHandler;
 
 impl Handler for MyHandler {
-	fn handle(&self, req: &Request) -> Response {
-		if let Some(addr) = req.peer_addr() {
-			println!("remote_addr=\"{}\"", addr);
-		}
-		if let Some(protocol) = req.protocol() {
-			println!("protocol=\"{}\"", match protocol {
-				Protocol::Http10 => "Http/1.0",
-				Protocol::Http11 => "Http/1.1",
-			});
-		}
-		if let Some(method) = req.method() {
-			println!("method=\"{}\"", match method {
-				Method::GET => "GET",
-				Method::POST => "POST",
-			});
-		}
-		if let Some(path) = req.path() {
-			println!("path=\"{}\"", str::from_utf8(path).unwrap());
-		}
-		if let Some(connection) = req.connection() {
-			println!("connection=\"{}\"", match connection {
-				Connection::Close => "close",
-				Connection::KeepAlive => "keep-alive",
-			});
-		}
-		if let Some(content_length) = req.content_length() {
-			println!("content_length=\"{}\"", content_length);
-		}
-		if let Some(post_data) = req.post_data() {
-			println!("post_data=\"{}\"", str::from_utf8(post_data).unwrap());
-		}
-		let header = req.header();
-		for line in &header.lines {
-			println!("\"{}\"", str::from_utf8(line.as_slice()).unwrap());
-		}
+	fn handle(&self, _: &Request) -> Response {
 		let mut html = HTML::new("akasabi", "ja");
 		let mut div = Tag::new("h1");
 		div.push_escape("It works!");
@@ -62,8 +29,12 @@ impl Handler for MyHandler {
 }
 
 fn main() {
+	let mut config = ServerConfig::new();
+	config.set_access_log(AccessLog::stdout(LogFormat::Combined));
+	let metrics = Arc::new(Metrics::new());
+	config.set_metrics(metrics.clone());
 	let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
 	for stream in listener.incoming() {
-		HttpHandler::new(MyHandler).handle(stream.unwrap());
+		HttpHandler::with_config(MetricsHandler::new(MyHandler, metrics.clone()), config.clone()).handle(stream.unwrap());
 	}
 }
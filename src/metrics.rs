@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use Handler;
+use Request;
+use Response;
+
+// Upper bounds in seconds, the same as the Prometheus client defaults.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+struct Histogram {
+	counts: [u64; 11],
+	count: u64,
+	sum: f64,
+}
+
+impl Histogram {
+	fn observe(&mut self, value: f64) {
+		if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| value <= bound) {
+			self.counts[i] += 1;
+		}
+		self.count += 1;
+		self.sum += value;
+	}
+}
+
+// Server statistics shared by every connection that has them in its
+// `ServerConfig`.
+pub struct Metrics {
+	connections_accepted: AtomicUsize,
+	connections_active: AtomicUsize,
+	parse_errors: AtomicUsize,
+	bytes_received: AtomicUsize,
+	bytes_sent: AtomicUsize,
+	requests: Mutex<BTreeMap<(&'static str, u32), u64>>,
+	latency: Mutex<Histogram>,
+}
+
+impl Default for Metrics {
+	fn default() -> Metrics {
+		Metrics::new()
+	}
+}
+
+impl Metrics {
+
+	pub fn new() -> Metrics {
+		Metrics {
+			connections_accepted: AtomicUsize::new(0),
+			connections_active: AtomicUsize::new(0),
+			parse_errors: AtomicUsize::new(0),
+			bytes_received: AtomicUsize::new(0),
+			bytes_sent: AtomicUsize::new(0),
+			requests: Mutex::new(BTreeMap::new()),
+			latency: Mutex::new(Histogram { counts: [0; 11], count: 0, sum: 0.0 }),
+		}
+	}
+
+	pub fn open_connection(&self) {
+		self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+		self.connections_active.fetch_add(1, Ordering::Relaxed);
+	}
+	pub fn close_connection(&self) {
+		self.connections_active.fetch_sub(1, Ordering::Relaxed);
+	}
+	pub fn parse_error(&self) {
+		self.parse_errors.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn request(&self, method: &'static str, status: u32, received: usize, sent: usize, duration: Duration) {
+		self.bytes_received.fetch_add(received, Ordering::Relaxed);
+		self.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+		if let Ok(mut requests) = self.requests.lock() {
+			*requests.entry((method, status)).or_insert(0) += 1;
+		}
+		if let Ok(mut latency) = self.latency.lock() {
+			latency.observe(duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9);
+		}
+	}
+
+	// Renders all metrics in the Prometheus text exposition format.
+	pub fn render(&self) -> String {
+		let mut buf = String::new();
+		let counters = [
+			("akasabi_connections_accepted_total", "counter", "Connections accepted.", &self.connections_accepted),
+			("akasabi_connections_active", "gauge", "Connections currently open.", &self.connections_active),
+			("akasabi_parse_errors_total", "counter", "Requests rejected as malformed or too large.", &self.parse_errors),
+			("akasabi_received_bytes_total", "counter", "Request bytes received.", &self.bytes_received),
+			("akasabi_sent_bytes_total", "counter", "Response bytes sent.", &self.bytes_sent),
+		];
+		for &(name, kind, help, value) in &counters {
+			let _ = write!(buf, "# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value.load(Ordering::Relaxed));
+		}
+		buf.push_str("# HELP akasabi_requests_total Requests handled.\n# TYPE akasabi_requests_total counter\n");
+		if let Ok(requests) = self.requests.lock() {
+			for (&(method, status), count) in requests.iter() {
+				let _ = writeln!(buf, "akasabi_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+			}
+		}
+		buf.push_str("# HELP akasabi_request_duration_seconds Request latency.\n# TYPE akasabi_request_duration_seconds histogram\n");
+		if let Ok(latency) = self.latency.lock() {
+			let mut cumulative = 0;
+			for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.counts.iter()) {
+				cumulative += count;
+				let _ = writeln!(buf, "akasabi_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+			}
+			let _ = writeln!(buf, "akasabi_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", latency.count);
+			let _ = writeln!(buf, "akasabi_request_duration_seconds_sum {}", latency.sum);
+			let _ = writeln!(buf, "akasabi_request_duration_seconds_count {}", latency.count);
+		}
+		buf
+	}
+}
+
+// Serves the metrics at a path, `/metrics` by default, and passes every
+// other request to the wrapped handler.
+pub struct MetricsHandler<T: Handler> {
+	handler: T,
+	metrics: Arc<Metrics>,
+	path: Vec<u8>,
+}
+
+impl<T: Handler> MetricsHandler<T> {
+	pub fn new(h: T, metrics: Arc<Metrics>) -> MetricsHandler<T> {
+		MetricsHandler {
+			handler: h,
+			metrics: metrics,
+			path: b"/metrics".to_vec(),
+		}
+	}
+	pub fn set_path(&mut self, path: &str) {
+		self.path = path.as_bytes().to_vec();
+	}
+}
+
+impl<T: Handler> Handler for MetricsHandler<T> {
+	fn handle(&self, req: &Request) -> Response {
+		let path = req.path().map(|p| p.split(|&x| x == b'?').next().unwrap_or(p));
+		if path == Some(self.path.as_slice()) {
+			let mut response = Response::from_string(self.metrics.render());
+			response.set_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8");
+			return response;
+		}
+		self.handler.handle(req)
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::time::Duration;
+	use super::Metrics;
+	use super::MetricsHandler;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Ok;
+	impl Handler for Ok {
+		fn handle(&self, _: &Request) -> Response {
+			Response::from_str("ok")
+		}
+	}
+	#[test]
+	fn test_render() {
+		let metrics = Arc::new(Metrics::new());
+		metrics.open_connection();
+		metrics.request("GET", 200, 100, 200, Duration::from_millis(20));
+		metrics.request("GET", 200, 100, 200, Duration::from_millis(300));
+		metrics.request("POST", 413, 50, 60, Duration::from_secs(20));
+		let handler = MetricsHandler::new(Ok, metrics.clone());
+		let text = String::from_utf8(with_request(&["GET /metrics?x HTTP/1.1"], None, |req| handler.handle(req)).content.unwrap()).unwrap();
+		assert!(text.contains("\nakasabi_connections_active 1\n"));
+		assert!(text.contains("\nakasabi_received_bytes_total 250\n"));
+		assert!(text.contains("\nakasabi_requests_total{method=\"GET\",status=\"200\"} 2\nakasabi_requests_total{method=\"POST\",status=\"413\"} 1\n"));
+		assert!(text.contains("\nakasabi_request_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+		assert!(text.contains("\nakasabi_request_duration_seconds_bucket{le=\"0.5\"} 2\n"));
+		assert!(text.contains("\nakasabi_request_duration_seconds_bucket{le=\"10\"} 2\nakasabi_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+		assert_eq!(Some(b"ok".to_vec()), with_request(&["GET / HTTP/1.1"], None, |req| handler.handle(req)).content);
+	}
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::str::from_utf8;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use Handler;
+use Request;
+use Response;
+
+// Allows bursts of up to `requests`, refilled evenly over `per`.
+#[derive(Copy,Clone,Debug)]
+pub struct Limit {
+	requests: u32,
+	per: Duration,
+}
+
+impl Limit {
+	// Panics if either is zero, which would leave no rate to refill at.
+	pub fn new(requests: u32, per: Duration) -> Limit {
+		assert!(requests > 0 && per > Duration::from_secs(0), "akasabi: a rate limit needs at least one request over a nonzero time");
+		Limit {
+			requests: requests,
+			per: per,
+		}
+	}
+	// Tokens added per second.
+	fn rate(&self) -> f64 {
+		self.requests as f64 / self.per.as_secs_f64()
+	}
+}
+
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Bucket {
+	fn refill(&mut self, limit: &Limit, now: Instant) {
+		let elapsed = now.duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.requests as f64);
+		self.updated = now;
+	}
+	// Seconds until the bucket holds the given number of tokens.
+	fn wait(&self, limit: &Limit, tokens: f64) -> u64 {
+		((tokens - self.tokens).max(0.0) / limit.rate()).ceil() as u64
+	}
+}
+
+struct Buckets {
+	buckets: HashMap<(usize, String), Bucket>,
+	swept: Instant,
+}
+
+type KeyFn = Box<Fn(&Request) -> Option<String> + Send + Sync>;
+
+enum Key {
+	PeerAddr,
+	// The address this many hops back in `X-Forwarded-For`, the last being
+	// added by the proxy nearest to the server.
+	ForwardedFor(usize),
+	Custom(KeyFn),
+}
+
+// Limits how often each client can call the wrapped handler, using a
+// token bucket per client and route. Clients are told apart by address,
+// unless `set_trusted_proxies` or `set_key` says otherwise, and requests
+// with no key at all are let through.
+pub struct RateLimit<T: Handler> {
+	handler: T,
+	limit: Limit,
+	routes: Vec<(Vec<u8>, Limit)>,
+	key: Key,
+	buckets: Mutex<Buckets>,
+	sweep_interval: Duration,
+}
+
+impl<T: Handler> RateLimit<T> {
+
+	pub fn new(h: T, limit: Limit) -> RateLimit<T> {
+		RateLimit {
+			handler: h,
+			limit: limit,
+			routes: Vec::new(),
+			key: Key::PeerAddr,
+			buckets: Mutex::new(Buckets {
+				buckets: HashMap::new(),
+				swept: Instant::now(),
+			}),
+			sweep_interval: Duration::from_secs(60),
+		}
+	}
+
+	// Applies a different limit to paths starting with the prefix. The
+	// longest matching prefix wins, and each route has its own buckets.
+	pub fn push_route(&mut self, prefix: &str, limit: Limit) {
+		self.routes.push((prefix.as_bytes().to_vec(), limit));
+	}
+	// Keys clients by `X-Forwarded-For` when the server is behind this many
+	// proxies. Only set this when every request does come through them, or
+	// clients could pick their own key.
+	pub fn set_trusted_proxies(&mut self, proxies: usize) {
+		self.key = if proxies == 0 { Key::PeerAddr } else { Key::ForwardedFor(proxies) };
+	}
+	// Keys clients by something else, such as an API key or user name.
+	pub fn set_key<F: Fn(&Request) -> Option<String> + Send + Sync + 'static>(&mut self, f: F) {
+		self.key = Key::Custom(Box::new(f));
+	}
+	// How often idle buckets are dropped, checked as requests come in.
+	pub fn set_sweep_interval(&mut self, interval: Duration) {
+		self.sweep_interval = interval;
+	}
+
+	// Drops buckets that have refilled completely, which are the same as
+	// new ones.
+	pub fn evict_idle(&self) {
+		if let Ok(mut buckets) = self.buckets.lock() {
+			self.sweep(&mut buckets, Instant::now());
+		}
+	}
+
+	fn sweep(&self, buckets: &mut Buckets, now: Instant) {
+		let limit = self.limit;
+		let routes = &self.routes;
+		buckets.buckets.retain(|&(route, _), bucket| {
+			let limit = routes.get(route).map_or(limit, |r| r.1);
+			bucket.refill(&limit, now);
+			bucket.tokens < limit.requests as f64
+		});
+		buckets.swept = now;
+	}
+
+	fn key(&self, req: &Request) -> Option<String> {
+		let peer_addr = || req.peer_addr().map(|a| a.ip().to_string());
+		match self.key {
+			Key::PeerAddr => peer_addr(),
+			Key::ForwardedFor(hops) => {
+				// Proxies may add a line of their own rather than append to
+				// the client's, so all lines count, in the order received.
+				let forwarded = req.header().get_strings(b"X-Forwarded-For").join(&b","[..]);
+				match from_utf8(&forwarded).ok().and_then(|f| f.rsplit(',').nth(hops - 1)) {
+					Some(addr) => Some(String::from(addr.trim())),
+					None => peer_addr(),
+				}
+			}
+			Key::Custom(ref f) => f(req),
+		}
+	}
+
+	fn route(&self, req: &Request) -> (usize, Limit) {
+		let path = req.path().unwrap_or(b"");
+		let route = self.routes.iter().enumerate()
+			.filter(|&(_, r)| path.starts_with(&r.0))
+			.max_by_key(|&(_, r)| r.0.len());
+		match route {
+			Some((i, r)) => (i, r.1),
+			None => (self.routes.len(), self.limit),
+		}
+	}
+
+	// Takes a token if `take` is set, or only checks that one is left, and
+	// returns the headers to send and whether the request may go ahead.
+	fn check(&self, req: &Request, take: bool, now: Instant) -> Option<(bool, Vec<(&'static str, String)>)> {
+		let key = self.key(req)?;
+		let (route, limit) = self.route(req);
+		let mut buckets = self.buckets.lock().ok()?;
+		if now.duration_since(buckets.swept) >= self.sweep_interval {
+			self.sweep(&mut buckets, now);
+		}
+		let bucket = buckets.buckets.entry((route, key)).or_insert(Bucket {
+			tokens: limit.requests as f64,
+			updated: now,
+		});
+		bucket.refill(&limit, now);
+		let allowed = bucket.tokens >= 1.0;
+		if allowed && take {
+			bucket.tokens -= 1.0;
+		}
+		let mut headers = vec![
+			("RateLimit-Limit", limit.requests.to_string()),
+			("RateLimit-Remaining", (bucket.tokens.floor() as u64).to_string()),
+			("RateLimit-Reset", bucket.wait(&limit, limit.requests as f64).to_string()),
+			("RateLimit-Policy", format!("{};w={}", limit.requests, limit.per.as_secs())),
+		];
+		if ! allowed {
+			headers.push(("Retry-After", bucket.wait(&limit, 1.0).to_string()));
+		}
+		Some((allowed, headers))
+	}
+
+	fn respond(&self, req: &Request, now: Instant) -> Response {
+		match self.check(req, true, now) {
+			Some((allowed, headers)) => {
+				let mut response = if allowed { self.handler.handle(req) } else { Response::from_status(429) };
+				set_headers(&mut response, &headers);
+				response
+			}
+			None => self.handler.handle(req),
+		}
+	}
+}
+
+fn set_headers(response: &mut Response, headers: &[(&'static str, String)]) {
+	for &(name, ref value) in headers {
+		response.set_header(name, value);
+	}
+}
+
+impl<T: Handler> Handler for RateLimit<T> {
+	fn handle(&self, req: &Request) -> Response {
+		self.respond(req, Instant::now())
+	}
+	// A client waiting to send a body is turned away early if it would be
+	// limited, without using a token before the request itself is handled.
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		if let Some((false, headers)) = self.check(req, false, Instant::now()) {
+			let mut response = Response::from_status(429);
+			set_headers(&mut response, &headers);
+			return Some(response)
+		}
+		self.handler.expect_continue(req)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use std::time::Instant;
+	use super::Limit;
+	use super::RateLimit;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Ok;
+	impl Handler for Ok {
+		fn handle(&self, _: &Request) -> Response {
+			Response::from_str("ok")
+		}
+	}
+	fn call(limiter: &RateLimit<Ok>, lines: &[&str]) -> Response {
+		with_request(lines, None, |req| limiter.handle(req))
+	}
+	// Requests are checked at given times, so refilling is tested without
+	// waiting for it.
+	fn call_at(limiter: &RateLimit<Ok>, lines: &[&str], now: Instant) -> Response {
+		with_request(lines, None, |req| limiter.respond(req, now))
+	}
+	fn rejects_early(limiter: &RateLimit<Ok>, lines: &[&str], now: Instant) -> bool {
+		with_request(lines, None, |req| limiter.check(req, false, now)).is_some_and(|c| ! c.0)
+	}
+	#[test]
+	fn test_limit() {
+		let mut limiter = RateLimit::new(Ok, Limit::new(2, Duration::from_secs(60)));
+		limiter.push_route("/api/login", Limit::new(1, Duration::from_millis(200)));
+		let start = Instant::now();
+		let response = call_at(&limiter, &["GET / HTTP/1.1"], start);
+		assert_eq!(200, response.status());
+		assert_eq!(Some("2"), response.get_header("RateLimit-Limit"));
+		assert_eq!(Some("1"), response.get_header("RateLimit-Remaining"));
+		assert_eq!(Some("30"), response.get_header("RateLimit-Reset"));
+		assert_eq!(Some("2;w=60"), response.get_header("RateLimit-Policy"));
+		assert_eq!(200, call_at(&limiter, &["GET /other HTTP/1.1"], start).status());
+		let response = call_at(&limiter, &["GET / HTTP/1.1"], start);
+		assert_eq!(429, response.status());
+		assert_eq!(Some("0"), response.get_header("RateLimit-Remaining"));
+		assert_eq!(Some("30"), response.get_header("Retry-After"));
+		assert!(rejects_early(&limiter, &["POST / HTTP/1.1", "Expect: 100-continue"], start));
+		let response = call_at(&limiter, &["GET / HTTP/1.1"], start + Duration::from_secs(30));
+		assert_eq!((200, Some("0")), (response.status(), response.get_header("RateLimit-Remaining")));
+		// Routes have their own buckets.
+		assert_eq!(200, call_at(&limiter, &["POST /api/login HTTP/1.1"], start).status());
+		assert!(rejects_early(&limiter, &["POST /api/login HTTP/1.1", "Expect: 100-continue"], start));
+		assert_eq!(Some("1"), call_at(&limiter, &["POST /api/login?x HTTP/1.1"], start + Duration::from_millis(100)).get_header("Retry-After"));
+		assert_eq!(200, call_at(&limiter, &["POST /api/login HTTP/1.1"], start + Duration::from_millis(200)).status());
+		let expect = with_request(&["POST /api/login HTTP/1.1", "Expect: 100-continue"], None, |req| limiter.expect_continue(req));
+		assert_eq!(Some(429), expect.map(|r| r.status()));
+	}
+	#[test]
+	#[should_panic(expected = "a rate limit needs at least one request")]
+	fn test_zero_limit() {
+		Limit::new(0, Duration::from_secs(60));
+	}
+	#[test]
+	fn test_keys() {
+		let mut limiter = RateLimit::new(Ok, Limit::new(1, Duration::from_secs(60)));
+		limiter.set_trusted_proxies(1);
+		assert_eq!(200, call(&limiter, &["GET / HTTP/1.1", "X-Forwarded-For: 10.0.0.1, 192.0.2.1"]).status());
+		assert_eq!(200, call(&limiter, &["GET / HTTP/1.1", "X-Forwarded-For: 192.0.2.2"]).status());
+		assert_eq!(429, call(&limiter, &["GET / HTTP/1.1", "X-Forwarded-For: 10.0.0.2, 192.0.2.1"]).status());
+		assert_eq!(200, call(&limiter, &["GET / HTTP/1.1"]).status());
+		assert_eq!(429, call(&limiter, &["GET / HTTP/1.1"]).status());
+		// A line added by the proxy counts, not one the client sent.
+		assert_eq!(200, call(&limiter, &["GET / HTTP/1.1", "X-Forwarded-For: 10.0.0.3", "X-Forwarded-For: 203.0.113.9"]).status());
+		assert_eq!(429, call(&limiter, &["GET / HTTP/1.1", "X-Forwarded-For: 10.0.0.4", "X-Forwarded-For: 203.0.113.9"]).status());
+		limiter.set_key(|req| req.header().get_string(b"X-Api-Key").map(|k| String::from_utf8_lossy(k).into_owned()));
+		assert_eq!(200, call(&limiter, &["GET / HTTP/1.1", "X-Api-Key: a"]).status());
+		assert_eq!(429, call(&limiter, &["GET / HTTP/1.1", "X-Api-Key: a"]).status());
+		let response = call(&limiter, &["GET / HTTP/1.1"]);
+		assert_eq!((200, None), (response.status(), response.get_header("RateLimit-Limit")));
+	}
+	#[test]
+	fn test_evict_idle() {
+		let mut limiter = RateLimit::new(Ok, Limit::new(1, Duration::from_millis(50)));
+		limiter.set_sweep_interval(Duration::from_secs(3600));
+		let start = Instant::now();
+		call_at(&limiter, &["GET / HTTP/1.1"], start);
+		let mut buckets = limiter.buckets.lock().unwrap();
+		limiter.sweep(&mut buckets, start + Duration::from_millis(49));
+		assert_eq!(1, buckets.buckets.len());
+		limiter.sweep(&mut buckets, start + Duration::from_millis(50));
+		assert_eq!(0, buckets.buckets.len());
+	}
+}
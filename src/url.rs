@@ -1,3 +1,5 @@
+use Params;
+
 const SPACE: u8 = 32;
 const PERCENT: u8 = 37;
 const PLUS: u8 = 43;
@@ -10,63 +12,450 @@ const LA: u8 = 97;
 const LF: u8 = 102;
 const LZ: u8 = 122;
 
-pub fn encode_percent(str: &[u8]) -> Vec<u8> {
-	let mut result: Vec<u8> = Vec::new();
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum DecodeError {
+	InvalidEscape(usize),
+	TruncatedEscape(usize),
+}
+
+fn is_unreserved(x: u8) -> bool {
+	match x {
+		ZERO ... NINE | UA ... UZ | LA ... LZ => true,
+		b'-' | b'.' | b'_' | b'~' => true,
+		_ => false,
+	}
+}
+
+fn is_sub_delim(x: u8) -> bool {
+	match x {
+		b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => true,
+		_ => false,
+	}
+}
+
+fn is_pchar(x: u8) -> bool {
+	is_unreserved(x) || is_sub_delim(x) || x == b':' || x == b'@'
+}
+
+fn encode(str: &[u8], keep: fn(u8) -> bool) -> Vec<u8> {
+	let mut result: Vec<u8> = Vec::with_capacity(str.len());
 	for &x in str.iter() {
-		match x {
-			ZERO ... NINE => { result.push(x); },
-			UA ... UZ => { result.push(x); },
-			LA ... LZ => { result.push(x); },
-			_ => {
-				let msb = x >> 4 & 0x0F;
-				let lsb = x & 0x0F;
-				result.push(PERCENT);
-				result.push(if msb < 10 { msb + ZERO } else { msb - 10 + UA });
-				result.push(if lsb < 10 { lsb + ZERO } else { lsb - 10 + UA });
-			},
+		if keep(x) {
+			result.push(x);
+		} else {
+			let msb = x >> 4 & 0x0F;
+			let lsb = x & 0x0F;
+			result.push(PERCENT);
+			result.push(if msb < 10 { msb + ZERO } else { msb - 10 + UA });
+			result.push(if lsb < 10 { lsb + ZERO } else { lsb - 10 + UA });
 		}
 	}
 	result
 }
 
-pub fn decode_percent(str: &[u8]) -> Vec<u8> {
-	let mut result: Vec<u8> = Vec::new();
-	str.iter().fold((0, 0), |(flag, sum), &x|
-		match x {
-			PLUS => { result.push(SPACE); (0, 0) },
-			PERCENT => (1, 0),
-			ZERO ... NINE => {
-				match flag {
-					1 => (2, x - ZERO),
-					2 => { result.push(sum * 16 + (x - ZERO)); (0, 0) },
-					_ => { result.push(x); (0, 0) },
+// Escapes everything except the RFC 3986 unreserved characters.
+pub fn encode_percent(str: &[u8]) -> Vec<u8> {
+	encode(str, is_unreserved)
+}
+
+// Escapes a single path segment; `/`, `?` and `#` are always escaped.
+pub fn encode_path_segment(str: &[u8]) -> Vec<u8> {
+	encode(str, is_pchar)
+}
+
+// Escapes a query name or value; `&`, `=`, `+` and `;` are escaped so
+// the result can be joined into `name=value&...` safely.
+pub fn encode_query(str: &[u8]) -> Vec<u8> {
+	encode(str, |x| match x {
+		b'&' | b'=' | b'+' | b';' => false,
+		b'/' | b'?' => true,
+		_ => is_pchar(x),
+	})
+}
+
+pub fn encode_fragment(str: &[u8]) -> Vec<u8> {
+	encode(str, |x| is_pchar(x) || x == b'/' || x == b'?')
+}
+
+pub fn encode_userinfo(str: &[u8]) -> Vec<u8> {
+	encode(str, |x| is_unreserved(x) || is_sub_delim(x) || x == b':')
+}
+
+// Encodes as `application/x-www-form-urlencoded`, with spaces as `+`.
+pub fn encode_form(str: &[u8]) -> Vec<u8> {
+	let mut result = encode(str, |x| match x {
+		ZERO ... NINE | UA ... UZ | LA ... LZ => true,
+		b'*' | b'-' | b'.' | b'_' | SPACE => true,
+		_ => false,
+	});
+	for x in result.iter_mut() {
+		if *x == SPACE { *x = PLUS; }
+	}
+	result
+}
+
+fn hex_value(x: u8) -> Option<u8> {
+	match x {
+		ZERO ... NINE => Some(x - ZERO),
+		UA ... UF => Some(x - UA + 10),
+		LA ... LF => Some(x - LA + 10),
+		_ => None,
+	}
+}
+
+fn decode(str: &[u8], plus: bool, strict: bool) -> Result<Vec<u8>, DecodeError> {
+	let mut result: Vec<u8> = Vec::with_capacity(str.len());
+	let mut i = 0;
+	while i < str.len() {
+		match str[i] {
+			PLUS if plus => { result.push(SPACE); },
+			PERCENT => {
+				if i + 2 >= str.len() {
+					if strict { return Err(DecodeError::TruncatedEscape(i)); }
+					result.push(PERCENT);
+				} else {
+					match (hex_value(str[i + 1]), hex_value(str[i + 2])) {
+						(Some(msb), Some(lsb)) => {
+							result.push(msb * 16 + lsb);
+							i += 2;
+						},
+						_ => {
+							if strict { return Err(DecodeError::InvalidEscape(i)); }
+							result.push(PERCENT);
+						},
+					}
 				}
 			},
-			UA ... UF => {
-				match flag {
-					1 => (2, x - UA + 10),
-					2 => { result.push(sum * 16 + (x - UA) + 10); (0, 0) },
-					_ => { result.push(x); (0, 0) },
-				}
+			x => { result.push(x); },
+		}
+		i += 1;
+	}
+	Ok(result)
+}
+
+// Decodes form data leniently: `+` becomes a space and malformed escapes
+// are kept as they are.
+pub fn decode_percent(str: &[u8]) -> Vec<u8> {
+	decode(str, true, false).unwrap()
+}
+
+// Decodes a URI component, rejecting malformed escapes. `+` is left as is.
+pub fn decode_strict(str: &[u8]) -> Result<Vec<u8>, DecodeError> {
+	decode(str, false, true)
+}
+
+// Decodes form data, rejecting malformed escapes.
+pub fn decode_form_strict(str: &[u8]) -> Result<Vec<u8>, DecodeError> {
+	decode(str, true, true)
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Form {
+	Origin,
+	Absolute,
+	Authority,
+	Asterisk,
+}
+
+#[derive(Clone,PartialEq,Debug)]
+pub struct Uri {
+	form: Form,
+	scheme: Option<String>,
+	userinfo: Option<String>,
+	host: Option<String>,
+	port: Option<u16>,
+	segments: Vec<String>,
+	query: Option<String>,
+	fragment: Option<String>,
+}
+
+fn decode_string(str: &[u8]) -> Option<String> {
+	String::from_utf8(decode_strict(str).ok()?).ok()
+}
+
+fn is_scheme(str: &[u8]) -> bool {
+	match str.first() {
+		Some(x) if x.is_ascii_alphabetic() => {
+			str.iter().all(|&x| x.is_ascii_alphanumeric() || x == b'+' || x == b'-' || x == b'.')
+		},
+		_ => false,
+	}
+}
+
+fn remove_dot_segments(segments: Vec<String>) -> Vec<String> {
+	let mut result: Vec<String> = Vec::new();
+	let count = segments.len();
+	for (i, segment) in segments.into_iter().enumerate() {
+		let last = i + 1 == count;
+		match segment.as_str() {
+			"." => {
+				if last { result.push(String::new()); }
 			},
-			LA ... LF => {
-				match flag {
-					1 => (2, x - LA + 10),
-					2 => { result.push(sum * 16 + (x - LA) + 10); (0, 0) },
-					_ => { result.push(x); (0, 0) },
-				}
+			".." => {
+				result.pop();
+				if last { result.push(String::new()); }
 			},
-			_ => { result.push(x); (0, 0) },
+			_ => { result.push(segment); },
 		}
-	);
+	}
 	result
 }
 
+impl Uri {
+
+	pub fn parse(target: &[u8]) -> Option<Uri> {
+		let mut uri = Uri {
+			form: Form::Origin,
+			scheme: None,
+			userinfo: None,
+			host: None,
+			port: None,
+			segments: Vec::new(),
+			query: None,
+			fragment: None,
+		};
+		if target == b"*" {
+			uri.form = Form::Asterisk;
+			return Some(uri);
+		}
+		let mut rest = target;
+		if let Some(pos) = rest.iter().position(|&x| x == b'#') {
+			uri.fragment = Some(decode_string(&rest[pos + 1 ..])?);
+			rest = &rest[.. pos];
+		}
+		if let Some(pos) = rest.iter().position(|&x| x == b'?') {
+			uri.query = Some(String::from_utf8(rest[pos + 1 ..].to_vec()).ok()?);
+			rest = &rest[.. pos];
+		}
+		if rest.first() != Some(&b'/') {
+			let colon = rest.iter().position(|&x| x == b':')?;
+			let after = &rest[colon + 1 ..];
+			if after.starts_with(b"//") && is_scheme(&rest[.. colon]) {
+				uri.form = Form::Absolute;
+				uri.scheme = Some(String::from_utf8(rest[.. colon].to_ascii_lowercase()).ok()?);
+				rest = &after[2 ..];
+				let end = rest.iter().position(|&x| x == b'/').unwrap_or(rest.len());
+				uri.parse_authority(&rest[.. end])?;
+				rest = &rest[end ..];
+			} else if uri.query.is_none() && uri.fragment.is_none() {
+				uri.form = Form::Authority;
+				uri.parse_authority(rest)?;
+				uri.port?;
+				return Some(uri);
+			} else {
+				return None;
+			}
+		}
+		if ! rest.is_empty() {
+			let mut segments: Vec<String> = Vec::new();
+			for segment in rest[1 ..].split(|&x| x == b'/') {
+				segments.push(decode_string(segment)?);
+			}
+			uri.segments = remove_dot_segments(segments);
+		}
+		Some(uri)
+	}
+
+	fn parse_authority(&mut self, authority: &[u8]) -> Option<()> {
+		let mut rest = authority;
+		if let Some(pos) = rest.iter().rposition(|&x| x == b'@') {
+			self.userinfo = Some(decode_string(&rest[.. pos])?);
+			rest = &rest[pos + 1 ..];
+		}
+		let host_end = if rest.first() == Some(&b'[') {
+			rest.iter().position(|&x| x == b']')? + 1
+		} else {
+			rest.iter().position(|&x| x == b':').unwrap_or(rest.len())
+		};
+		if host_end == 0 {
+			return None;
+		}
+		self.host = Some(String::from_utf8(rest[.. host_end].to_ascii_lowercase()).ok()?);
+		let port = &rest[host_end ..];
+		if port.len() > 1 {
+			if port[0] != b':' || ! port[1 ..].iter().all(|x| x.is_ascii_digit()) {
+				return None;
+			}
+			self.port = Some(String::from_utf8(port[1 ..].to_vec()).ok()?.parse().ok()?);
+		} else if port.len() == 1 && port[0] != b':' {
+			return None;
+		}
+		Some(())
+	}
+
+	pub fn form(&self) -> Form {
+		self.form
+	}
+	pub fn scheme(&self) -> Option<&str> {
+		self.scheme.as_deref()
+	}
+	pub fn userinfo(&self) -> Option<&str> {
+		self.userinfo.as_deref()
+	}
+	pub fn host(&self) -> Option<&str> {
+		self.host.as_deref()
+	}
+	pub fn port(&self) -> Option<u16> {
+		self.port
+	}
+	pub fn segments(&self) -> &[String] {
+		self.segments.as_slice()
+	}
+	pub fn path(&self) -> String {
+		let mut path = String::new();
+		if let Form::Asterisk = self.form {
+			path.push('*');
+		} else if self.segments.is_empty() {
+			if let Form::Origin = self.form {
+				path.push('/');
+			}
+		} else {
+			for segment in &self.segments {
+				path.push('/');
+				path.push_str(String::from_utf8(encode_path_segment(segment.as_bytes())).unwrap().as_str());
+			}
+		}
+		path
+	}
+	pub fn query(&self) -> Option<&str> {
+		self.query.as_deref()
+	}
+	pub fn query_params(&self) -> Params {
+		Params { query: self.query.as_ref().map(|x| x.as_bytes()) }
+	}
+	pub fn fragment(&self) -> Option<&str> {
+		self.fragment.as_deref()
+	}
+}
+
+impl ToString for Uri {
+	fn to_string(&self) -> String {
+		let mut uri = String::new();
+		if let Some(ref scheme) = self.scheme {
+			uri.push_str(scheme.as_str());
+			uri.push_str("://");
+		} else if self.host.is_some() && self.form == Form::Origin {
+			// A network-path reference, as the host would otherwise be read
+			// as the start of the path.
+			uri.push_str("//");
+		}
+		if let Some(ref userinfo) = self.userinfo {
+			uri.push_str(String::from_utf8(encode_userinfo(userinfo.as_bytes())).unwrap().as_str());
+			uri.push('@');
+		}
+		if let Some(ref host) = self.host {
+			uri.push_str(host.as_str());
+		}
+		if let Some(port) = self.port {
+			uri.push(':');
+			uri.push_str(port.to_string().as_str());
+		}
+		uri.push_str(self.path().as_str());
+		if let Some(ref query) = self.query {
+			uri.push('?');
+			uri.push_str(query.as_str());
+		}
+		if let Some(ref fragment) = self.fragment {
+			uri.push('#');
+			uri.push_str(String::from_utf8(encode_fragment(fragment.as_bytes())).unwrap().as_str());
+		}
+		uri
+	}
+}
+
+pub struct UriBuilder {
+	uri: Uri,
+}
+
+impl Default for UriBuilder {
+	fn default() -> UriBuilder {
+		UriBuilder::new()
+	}
+}
+
+impl UriBuilder {
+	pub fn new() -> UriBuilder {
+		UriBuilder {
+			uri: Uri {
+				form: Form::Origin,
+				scheme: None,
+				userinfo: None,
+				host: None,
+				port: None,
+				segments: Vec::new(),
+				query: None,
+				fragment: None,
+			},
+		}
+	}
+	pub fn scheme(&mut self, scheme: &str) -> &mut UriBuilder {
+		self.uri.scheme = Some(scheme.to_ascii_lowercase());
+		self.uri.form = Form::Absolute;
+		self
+	}
+	pub fn userinfo(&mut self, userinfo: &str) -> &mut UriBuilder {
+		self.uri.userinfo = Some(String::from(userinfo));
+		self
+	}
+	// Without a scheme, this builds authority-form such as `example.com:443`,
+	// or a network-path reference such as `//example.com/a` once anything
+	// follows the host.
+	pub fn host(&mut self, host: &str) -> &mut UriBuilder {
+		self.uri.host = Some(host.to_ascii_lowercase());
+		if self.uri.scheme.is_none() {
+			self.uri.form = Form::Authority;
+		}
+		self
+	}
+	pub fn port(&mut self, port: u16) -> &mut UriBuilder {
+		self.uri.port = Some(port);
+		self
+	}
+	pub fn push_segment(&mut self, segment: &str) -> &mut UriBuilder {
+		self.uri.segments.push(String::from(segment));
+		self
+	}
+	pub fn push_query(&mut self, name: &str, value: &str) -> &mut UriBuilder {
+		let mut query = self.uri.query.take().unwrap_or_default();
+		if ! query.is_empty() {
+			query.push('&');
+		}
+		query.push_str(String::from_utf8(encode_query(name.as_bytes())).unwrap().as_str());
+		query.push('=');
+		query.push_str(String::from_utf8(encode_query(value.as_bytes())).unwrap().as_str());
+		self.uri.query = Some(query);
+		self
+	}
+	pub fn fragment(&mut self, fragment: &str) -> &mut UriBuilder {
+		self.uri.fragment = Some(String::from(fragment));
+		self
+	}
+	pub fn build(&self) -> Uri {
+		let mut uri = self.uri.clone();
+		if uri.form == Form::Authority && (! uri.segments.is_empty() || uri.query.is_some() || uri.fragment.is_some()) {
+			uri.form = Form::Origin;
+		}
+		uri
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use std::str;
 	use super::encode_percent;
 	use super::decode_percent;
+	use super::encode_path_segment;
+	use super::encode_query;
+	use super::encode_fragment;
+	use super::encode_userinfo;
+	use super::encode_form;
+	use super::decode_strict;
+	use super::decode_form_strict;
+	use super::DecodeError;
+	use quickcheck::quickcheck;
+	use super::Form;
+	use super::Uri;
+	use super::UriBuilder;
 	#[test]
 	fn test_encode_percent() {
 		assert_eq!("%E3%81%82%E3%81%84%E3%81%86%E3%81%88%E3%81%8A", str::from_utf8(encode_percent("あいうえお".as_bytes()).as_slice()).unwrap());
@@ -83,4 +472,117 @@ mod tests {
 		assert_eq!("たちつてと", str::from_utf8(decode_percent(b"%E3%81%9F%E3%81%A1%E3%81%A4%E3%81%A6%E3%81%A8").as_slice()).unwrap());
 		assert_eq!("なにぬねの", str::from_utf8(decode_percent(b"%E3%81%AA%E3%81%AB%E3%81%AC%E3%81%AD%E3%81%AE").as_slice()).unwrap());
 	}
+	#[test]
+	fn test_parse_origin_form() {
+		let uri = Uri::parse(b"/a/./b/../c%20d/?x=1&y=2#top").unwrap();
+		assert_eq!(Form::Origin, uri.form());
+		assert_eq!(None, uri.host());
+		assert_eq!(&["a", "c d", ""], uri.segments());
+		assert_eq!("/a/c%20d/", uri.path());
+		assert_eq!(Some("x=1&y=2"), uri.query());
+		assert_eq!(Some("top"), uri.fragment());
+		assert_eq!(2, uri.query_params().count());
+		assert_eq!("/", Uri::parse(b"/a/..").unwrap().path());
+		assert_eq!("/", Uri::parse(b"/../..").unwrap().path());
+	}
+	#[test]
+	fn test_parse_absolute_form() {
+		let uri = Uri::parse(b"HTTP://user@Example.COM:8080/index?q").unwrap();
+		assert_eq!(Form::Absolute, uri.form());
+		assert_eq!(Some("http"), uri.scheme());
+		assert_eq!(Some("user"), uri.userinfo());
+		assert_eq!(Some("example.com"), uri.host());
+		assert_eq!(Some(8080), uri.port());
+		assert_eq!("/index", uri.path());
+		assert_eq!(Some("q"), uri.query());
+		let uri = Uri::parse(b"http://[::1]").unwrap();
+		assert_eq!(Some("[::1]"), uri.host());
+		assert_eq!(None, uri.port());
+		assert_eq!("", uri.path());
+		assert!(Uri::parse(b"http://example.com:80x/").is_none());
+	}
+	#[test]
+	fn test_parse_authority_and_asterisk_form() {
+		let uri = Uri::parse(b"example.com:443").unwrap();
+		assert_eq!(Form::Authority, uri.form());
+		assert_eq!(Some("example.com"), uri.host());
+		assert_eq!(Some(443), uri.port());
+		assert_eq!("example.com:443", uri.to_string());
+		assert!(Uri::parse(b"example.com").is_none());
+		assert_eq!(Form::Asterisk, Uri::parse(b"*").unwrap().form());
+		assert_eq!("*", Uri::parse(b"*").unwrap().to_string());
+	}
+	#[test]
+	fn test_uri_builder() {
+		let uri = UriBuilder::new()
+			.scheme("http")
+			.host("example.com")
+			.port(8080)
+			.push_segment("a b")
+			.push_segment("c")
+			.push_query("q", "x&y")
+			.fragment("f")
+			.build();
+		assert_eq!("http://example.com:8080/a%20b/c?q=x%26y#f", uri.to_string());
+		assert_eq!(uri, Uri::parse(uri.to_string().as_bytes()).unwrap());
+		let uri = UriBuilder::new().push_segment("index").build();
+		assert_eq!("/index", uri.to_string());
+		assert_eq!("example.com:443", UriBuilder::new().host("example.com").port(443).build().to_string());
+		assert_eq!("//example.com/a", UriBuilder::new().host("example.com").push_segment("a").build().to_string());
+		assert_eq!("//example.com/?q=1", UriBuilder::new().host("example.com").push_query("q", "1").build().to_string());
+	}
+	#[test]
+	fn test_parse_strict() {
+		assert_eq!(None, Uri::parse(b"/a%2"));
+		assert_eq!(&["a+b"], Uri::parse(b"/a+b").unwrap().segments());
+	}
+	#[test]
+	fn test_encode_components() {
+		assert_eq!(b"a-._~b%20".to_vec(), encode_percent(b"a-._~b "));
+		assert_eq!(b"a:b@c%2Fd%3F%23".to_vec(), encode_path_segment(b"a:b@c/d?#"));
+		assert_eq!(b"a/b?%26%3D%2B%3B".to_vec(), encode_query(b"a/b?&=+;"));
+		assert_eq!(b"a/b?%23".to_vec(), encode_fragment(b"a/b?#"));
+		assert_eq!(b"user:pass%40".to_vec(), encode_userinfo(b"user:pass@"));
+		assert_eq!(b"a+b%2B%26*".to_vec(), encode_form(b"a b+&*"));
+	}
+	#[test]
+	fn test_decode_malformed() {
+		assert_eq!(b"%G1%4 a".to_vec(), decode_percent(b"%G1%4+a"));
+		assert_eq!(b"%".to_vec(), decode_percent(b"%"));
+		assert_eq!(Err(DecodeError::InvalidEscape(1)), decode_strict(b"a%G1"));
+		assert_eq!(Err(DecodeError::TruncatedEscape(1)), decode_strict(b"a%4"));
+		assert_eq!(Ok(b"a+b".to_vec()), decode_strict(b"a+b"));
+		assert_eq!(Ok(b"a b".to_vec()), decode_form_strict(b"a+b"));
+	}
+	#[test]
+	fn test_round_trip() {
+		fn prop(str: Vec<u8>) -> bool {
+			let str = &str[..];
+			Ok(str.to_vec()) == decode_strict(encode_percent(str).as_slice()) &&
+				Ok(str.to_vec()) == decode_strict(encode_path_segment(str).as_slice()) &&
+				Ok(str.to_vec()) == decode_strict(encode_query(str).as_slice()) &&
+				Ok(str.to_vec()) == decode_strict(encode_fragment(str).as_slice()) &&
+				Ok(str.to_vec()) == decode_strict(encode_userinfo(str).as_slice()) &&
+				Ok(str.to_vec()) == decode_form_strict(encode_form(str).as_slice()) &&
+				str.to_vec() == decode_percent(encode_form(str).as_slice())
+		}
+		quickcheck(prop as fn(Vec<u8>) -> bool);
+	}
+	#[test]
+	fn test_uri_round_trip() {
+		fn prop(segments: Vec<String>, query: String, fragment: String) -> bool {
+			let mut builder = UriBuilder::new();
+			builder.scheme("http").host("example.com");
+			for segment in &segments {
+				if segment != "." && segment != ".." {
+					builder.push_segment(segment.as_str());
+				}
+			}
+			builder.push_query("q", query.as_str()).fragment(fragment.as_str());
+			let uri = builder.build();
+			let parsed = Uri::parse(uri.to_string().as_bytes()).unwrap();
+			uri == parsed && parsed.query_params().next().unwrap().value() == query
+		}
+		quickcheck(prop as fn(Vec<String>, String, String) -> bool);
+	}
 }
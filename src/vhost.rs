@@ -0,0 +1,181 @@
+use Handler;
+use Request;
+use Response;
+
+use http::Protocol;
+
+pub fn strip_port(host: &[u8]) -> &[u8] {
+	if host.first() == Some(&b'[') {
+		if let Some(pos) = host.iter().position(|&x| x == b']') {
+			return &host[.. pos + 1]
+		}
+		return host
+	}
+	if let Some(pos) = host.iter().rposition(|&x| x == b':') {
+		return &host[.. pos]
+	}
+	host
+}
+
+enum Pattern {
+	Exact(Vec<u8>),
+	Wildcard(Vec<u8>),
+}
+
+impl Pattern {
+	fn new(pattern: &str) -> Pattern {
+		let pattern = pattern.as_bytes();
+		if pattern.starts_with(b"*.") {
+			Pattern::Wildcard(pattern[1 ..].to_ascii_lowercase())
+		} else {
+			Pattern::Exact(strip_port(pattern).to_ascii_lowercase())
+		}
+	}
+	fn matches(&self, host: &[u8]) -> bool {
+		match *self {
+			Pattern::Exact(ref name) => host.eq_ignore_ascii_case(name),
+			Pattern::Wildcard(ref suffix) => {
+				host.len() > suffix.len() &&
+					host[host.len() - suffix.len() ..].eq_ignore_ascii_case(suffix)
+			},
+		}
+	}
+}
+
+pub struct VirtualHosts {
+	hosts: Vec<(Pattern, Box<Handler>)>,
+	default: Box<Handler>,
+}
+
+impl VirtualHosts {
+	pub fn new<T: Handler + 'static>(default: T) -> VirtualHosts {
+		VirtualHosts {
+			hosts: Vec::new(),
+			default: Box::new(default),
+		}
+	}
+	pub fn push_host<T: Handler + 'static>(&mut self, pattern: &str, handler: T) {
+		self.hosts.push((Pattern::new(pattern), Box::new(handler)));
+	}
+	fn find(&self, host: &[u8]) -> &Handler {
+		let host = strip_port(host);
+		let mut wildcard: Option<&Handler> = None;
+		let mut longest = 0;
+		for (pattern, handler) in &self.hosts {
+			if ! pattern.matches(host) {
+				continue;
+			}
+			match *pattern {
+				Pattern::Exact(_) => return handler.as_ref(),
+				Pattern::Wildcard(ref suffix) => {
+					if suffix.len() > longest {
+						wildcard = Some(handler.as_ref());
+						longest = suffix.len();
+					}
+				},
+			}
+		}
+		match wildcard {
+			Some(handler) => handler,
+			None => self.default.as_ref(),
+		}
+	}
+}
+
+impl VirtualHosts {
+	fn select(&self, req: &Request) -> Result<&Handler, Response> {
+		if let Some(uri) = req.uri() {
+			if let Some(host) = uri.host() {
+				return Ok(self.find(host.as_bytes()));
+			}
+		}
+		match req.host() {
+			Some(host) => Ok(self.find(host)),
+			None => {
+				if let Some(Protocol::Http11) = req.protocol() {
+					Err(Response::from_status(400))
+				} else {
+					Ok(self.default.as_ref())
+				}
+			},
+		}
+	}
+}
+
+impl Handler for VirtualHosts {
+	fn handle(&self, req: &Request) -> Response {
+		match self.select(req) {
+			Ok(handler) => handler.handle(req),
+			Err(response) => response,
+		}
+	}
+	fn expect_continue(&self, req: &Request) -> Option<Response> {
+		match self.select(req) {
+			Ok(handler) => handler.expect_continue(req),
+			Err(response) => Some(response),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::strip_port;
+	use super::Pattern;
+	use super::VirtualHosts;
+	use http::with_request;
+	use Handler;
+	use Request;
+	use Response;
+	struct Name(&'static str);
+	impl Handler for Name {
+		fn handle(&self, _: &Request) -> Response {
+			Response::from_str(self.0)
+		}
+	}
+	fn hosts() -> VirtualHosts {
+		let mut hosts = VirtualHosts::new(Name("default"));
+		hosts.push_host("example.com", Name("example"));
+		hosts.push_host("*.example.com", Name("sub"));
+		hosts.push_host("*.api.example.com", Name("api"));
+		hosts
+	}
+	fn call(hosts: &VirtualHosts, lines: &[&str]) -> (u32, Vec<u8>) {
+		let response = with_request(lines, None, |req| hosts.handle(req));
+		(response.status(), response.content.unwrap_or_default())
+	}
+	#[test]
+	fn test_strip_port() {
+		assert_eq!(b"example.com", strip_port(b"example.com:8080"));
+		assert_eq!(b"example.com", strip_port(b"example.com"));
+		assert_eq!(b"[::1]", strip_port(b"[::1]:8080"));
+		assert_eq!(b"[::1]", strip_port(b"[::1]"));
+	}
+	#[test]
+	fn test_pattern() {
+		assert!(Pattern::new("example.com").matches(b"EXAMPLE.com"));
+		assert!(! Pattern::new("example.com").matches(b"www.example.com"));
+		assert!(Pattern::new("*.example.com").matches(b"www.Example.com"));
+		assert!(Pattern::new("*.example.com").matches(b"a.b.example.com"));
+		assert!(! Pattern::new("*.example.com").matches(b"example.com"));
+		assert!(! Pattern::new("*.example.com").matches(b"badexample.com"));
+	}
+	#[test]
+	fn test_dispatch() {
+		let hosts = hosts();
+		assert_eq!((200, b"example".to_vec()), call(&hosts, &["GET / HTTP/1.1", "Host: Example.com"]));
+		assert_eq!((200, b"example".to_vec()), call(&hosts, &["GET / HTTP/1.1", "Host: example.com:8080"]));
+		assert_eq!((200, b"sub".to_vec()), call(&hosts, &["GET / HTTP/1.1", "Host: www.example.com:8080"]));
+		assert_eq!((200, b"api".to_vec()), call(&hosts, &["GET / HTTP/1.1", "Host: v1.api.example.com"]));
+		assert_eq!((200, b"default".to_vec()), call(&hosts, &["GET / HTTP/1.1", "Host: example.org"]));
+		// The host of an absolute-form target wins over the Host header.
+		assert_eq!((200, b"sub".to_vec()), call(&hosts, &["GET http://www.example.com/ HTTP/1.1", "Host: example.org"]));
+	}
+	#[test]
+	fn test_missing_host() {
+		let hosts = hosts();
+		assert_eq!(400, call(&hosts, &["GET / HTTP/1.1"]).0);
+		let expect = with_request(&["POST / HTTP/1.1", "Expect: 100-continue"], None, |req| hosts.expect_continue(req));
+		assert_eq!(Some(400), expect.map(|r| r.status()));
+		assert_eq!((200, b"default".to_vec()), call(&hosts, &["GET / HTTP/1.0"]));
+	}
+}